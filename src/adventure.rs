@@ -0,0 +1,433 @@
+use crate::{split_ascii, AsciiOutput, Direction, Intcode, RunState};
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
+use pathfinding::prelude::bfs;
+
+// Limits for each command, so that one stuck in a loop (or scribbling over
+// memory) gives up rather than holding everything up
+const COMMAND_FUEL: u64 = 1_000_000;
+const MAX_MEMORY: usize = 1 << 20;
+
+fn direction_name(dir: Direction) -> &'static str {
+    match dir {
+        Direction::North => "north",
+        Direction::South => "south",
+        Direction::East => "east",
+        Direction::West => "west",
+    }
+}
+
+fn parse_direction(name: &str) -> Option<Direction> {
+    match name {
+        "north" => Some(Direction::North),
+        "south" => Some(Direction::South),
+        "east" => Some(Direction::East),
+        "west" => Some(Direction::West),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Room {
+    pub name: String,
+    pub description: Vec<String>,
+    pub doors: Vec<Direction>,
+    pub items: Vec<String>,
+}
+
+// Which way a weight check failed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Weight {
+    TooLight,
+    TooHeavy,
+}
+
+// What a game printed in response to a command: the rooms it described (in
+// order, usually just the one the player is now in) and everything else
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Scene {
+    pub rooms: Vec<Room>,
+    pub messages: Vec<String>,
+}
+
+impl Scene {
+    // Rooms look like this, with any other lines taken as messages:
+    //
+    //   == Hallway ==
+    //   A description.
+    //
+    //   Doors here lead:
+    //   - north
+    //
+    //   Items here:
+    //   - mug
+    //
+    //   Command?
+    pub fn parse(text: &str) -> Self {
+        let mut scene = Scene::default();
+        let mut list: Option<&str> = None;
+        for line in text.lines().map(str::trim) {
+            let room = scene.rooms.last_mut();
+            if let Some(name) = line.strip_prefix("== ").and_then(|l| l.strip_suffix(" ==")) {
+                scene.rooms.push(Room {
+                    name: name.to_string(),
+                    ..Room::default()
+                });
+                list = Some("description");
+            } else if line.is_empty() || line == "Command?" {
+                list = None;
+            } else if line == "Doors here lead:" || line == "Items here:" {
+                list = Some(line);
+            } else if let (Some(room), Some(list)) = (room, list) {
+                match (list, line.strip_prefix("- ")) {
+                    ("Doors here lead:", Some(door)) => room.doors.extend(parse_direction(door)),
+                    ("Items here:", Some(item)) => room.items.push(item.to_string()),
+                    ("description", _) => room.description.push(line.to_string()),
+                    _ => scene.messages.push(line.to_string()),
+                }
+            } else {
+                scene.messages.push(line.to_string());
+            }
+        }
+        scene
+    }
+
+    // The room the player ended up in, if described
+    pub fn room(&self) -> Option<&Room> {
+        self.rooms.last()
+    }
+
+    // Whether the player was sent somewhere other than the room they moved
+    // into (e.g. back from a checkpoint)
+    pub fn ejected(&self) -> bool {
+        self.rooms.len() > 1
+    }
+
+    pub fn weight(&self) -> Option<Weight> {
+        self.messages.iter().find_map(|line| {
+            if line.contains("heavier than the detected") {
+                Some(Weight::TooLight)
+            } else if line.contains("lighter than the detected") {
+                Some(Weight::TooHeavy)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+// A game in progress. Cloning it (cheaply) gives a save point.
+#[derive(Clone)]
+pub struct Player {
+    pub intcode: Intcode,
+}
+
+impl Player {
+    pub fn new(mut intcode: Intcode) -> Self {
+        intcode.compile();
+        intcode.set_max_memory(Some(MAX_MEMORY));
+        Self { intcode }
+    }
+
+    // Sends a command (without its newline, or nothing if empty), returning
+    // what the game said and whether it's still waiting for commands
+    pub fn send(&mut self, cmd: &str) -> (Scene, bool) {
+        let input: Vec<_> = match cmd {
+            "" => vec![],
+            _ => cmd.chars().chain(['\n']).map(|c| c as i64).collect(),
+        };
+        let mut input = input.into_iter();
+        let mut output = vec![];
+        self.intcode.set_fuel(Some(COMMAND_FUEL));
+        // A fault ends the game as surely as halting does
+        let state = self.intcode.try_run(|| input.next(), |v| output.push(v));
+        // Values outside the ASCII range aren't part of the text
+        let text: String = split_ascii(&output)
+            .into_iter()
+            .filter_map(|part| match part {
+                AsciiOutput::Text(text) => Some(text),
+                AsciiOutput::Value(_) => None,
+            })
+            .collect();
+        (Scene::parse(&text), state == Ok(RunState::NeedsInput))
+    }
+
+    pub fn go(&mut self, dir: Direction) -> (Scene, bool) {
+        self.send(direction_name(dir))
+    }
+}
+
+// A text adventure explored room by room, with what was learned on the way
+pub struct Adventure {
+    start: Player,
+    start_room: String,
+    pub rooms: HashMap<String, Room>,
+    doors: HashMap<(String, Direction), String>,
+    // Items that can be safely picked up, and where they are
+    pub items: HashMap<String, String>,
+    pub deadly: HashSet<String>,
+    // Room and door guarded by a weight check
+    pub checkpoint: Option<(String, Direction)>,
+}
+
+impl Adventure {
+    // Visits every room, trying out every item on a copy of the game. None if
+    // the game doesn't start in a room.
+    pub fn explore(intcode: Intcode) -> Option<Self> {
+        let mut start = Player::new(intcode);
+        let (scene, true) = start.send("") else {
+            return None;
+        };
+        let room = scene.room()?.clone();
+        let mut adventure = Adventure {
+            start: start.clone(),
+            start_room: room.name.clone(),
+            rooms: HashMap::new(),
+            doors: HashMap::new(),
+            items: HashMap::new(),
+            deadly: HashSet::new(),
+            checkpoint: None,
+        };
+
+        let mut todo = vec![(start, room)];
+        while let Some((player, room)) = todo.pop() {
+            if adventure.rooms.contains_key(&room.name) {
+                continue;
+            }
+            for item in &room.items {
+                if adventure.is_safe(&player, &room, item) {
+                    adventure.items.insert(item.clone(), room.name.clone());
+                } else {
+                    adventure.deadly.insert(item.clone());
+                }
+            }
+            for &door in &room.doors {
+                let mut next = player.clone();
+                let (scene, true) = next.go(door) else {
+                    continue;
+                };
+                if scene.ejected() {
+                    adventure.checkpoint = Some((room.name.clone(), door));
+                } else if let Some(next_room) = scene.room() {
+                    let key = (room.name.clone(), door);
+                    adventure.doors.insert(key, next_room.name.clone());
+                    todo.push((next, next_room.clone()));
+                }
+            }
+            adventure.rooms.insert(room.name.clone(), room);
+        }
+        Some(adventure)
+    }
+
+    // Whether the game carries on normally after taking the item, letting
+    // the player leave the room
+    fn is_safe(&self, player: &Player, room: &Room, item: &str) -> bool {
+        let mut player = player.clone();
+        if !player.send(&format!("take {}", item)).1 {
+            return false;
+        }
+        match room.doors.first() {
+            Some(&door) => matches!(player.go(door), (scene, true) if scene.room().is_some()),
+            None => true,
+        }
+    }
+
+    pub fn route(&self, from: &str, to: &str) -> Option<Vec<Direction>> {
+        let rooms = bfs(
+            &from.to_string(),
+            |room| {
+                self.rooms[room]
+                    .doors
+                    .iter()
+                    .filter_map(|&door| self.doors.get(&(room.clone(), door)).cloned())
+                    .collect::<Vec<_>>()
+            },
+            |room| room == to,
+        )?;
+        rooms
+            .iter()
+            .tuple_windows()
+            .map(|(a, b)| {
+                self.rooms[a]
+                    .doors
+                    .iter()
+                    .copied()
+                    .find(|&door| self.doors.get(&(a.clone(), door)) == Some(b))
+            })
+            .collect()
+    }
+
+    // Moves the player between rooms, or returns None if the game doesn't
+    // take them where expected
+    fn walk(&self, player: &mut Player, from: &str, to: &str) -> Option<()> {
+        let mut room = from.to_string();
+        for dir in self.route(from, to)? {
+            let (scene, true) = player.go(dir) else {
+                return None;
+            };
+            room = scene.room()?.name.clone();
+        }
+        (room == to).then_some(())
+    }
+
+    // Picks up the safe items and takes them through the checkpoint, trying
+    // combinations from half the items outwards. A combination that's too
+    // heavy rules out everything containing it, and one that's too light
+    // everything it contains. Returns the items that got through and what
+    // the game said when they did.
+    pub fn solve(&self) -> Option<(Vec<String>, Scene)> {
+        let (checkpoint, door) = self.checkpoint.clone()?;
+        let items: Vec<_> = self.items.keys().sorted().cloned().collect();
+
+        let mut player = self.start.clone();
+        let mut room = self.start_room.clone();
+        for (item, next) in items.iter().map(|item| (item, &self.items[item])) {
+            self.walk(&mut player, &room, next)?;
+            let (_, true) = player.send(&format!("take {}", item)) else {
+                return None;
+            };
+            room = next.clone();
+        }
+        self.walk(&mut player, &room, &checkpoint)?;
+
+        let half = items.len() / 2;
+        let combinations = (0..=items.len())
+            .sorted_by_key(|k| k.abs_diff(half))
+            .flat_map(|k| (0..items.len()).combinations(k));
+        let (mut heavy, mut light): (Vec<Vec<usize>>, Vec<Vec<usize>>) = (vec![], vec![]);
+        let within = |a: &[usize], b: &[usize]| a.iter().all(|i| b.contains(i));
+        for carried in combinations {
+            if heavy.iter().any(|h| within(h, &carried))
+                || light.iter().any(|l| within(&carried, l))
+            {
+                continue;
+            }
+            let mut attempt = player.clone();
+            for i in (0..items.len()).filter(|i| !carried.contains(i)) {
+                attempt.send(&format!("drop {}", items[i]));
+            }
+            let (scene, _) = attempt.go(door);
+            match scene.weight() {
+                Some(Weight::TooHeavy) => heavy.push(carried),
+                Some(Weight::TooLight) => light.push(carried),
+                None if scene.ejected() => {}
+                None => {
+                    let carried = carried.iter().map(|&i| items[i].clone()).collect();
+                    return Some((carried, scene));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let scene = Scene::parse(
+            "
+
+== Pressure-Sensitive Floor ==
+Analyzing...
+
+Doors here lead:
+- east
+
+A loud, robotic voice says \"Alert! Droids on this ship are heavier than the detected value!\" and you are ejected back to the checkpoint.
+
+== Security Checkpoint ==
+In the next room, a pressure-sensitive floor will verify your identity.
+
+Doors here lead:
+- north
+- west
+
+Items here:
+- mug
+- space heater
+
+Command?
+",
+        );
+        assert_eq!(scene.rooms.len(), 2);
+        assert!(scene.ejected());
+        assert_eq!(scene.weight(), Some(Weight::TooLight));
+        assert_eq!(
+            scene.room(),
+            Some(&Room {
+                name: "Security Checkpoint".to_string(),
+                description: vec![
+                    "In the next room, a pressure-sensitive floor will verify your identity."
+                        .to_string()
+                ],
+                doors: vec![Direction::North, Direction::West],
+                items: vec!["mug".to_string(), "space heater".to_string()],
+            })
+        );
+        assert_eq!(scene.messages.len(), 1);
+    }
+
+    #[test]
+    fn test_explore() {
+        let code = crate::template::read_file("examples", crate::day!(25));
+        let adventure = Adventure::explore(Intcode::new(&code)).unwrap();
+        assert_eq!(
+            adventure.deadly.iter().sorted().collect::<Vec<_>>(),
+            [
+                "escape pod",
+                "giant electromagnet",
+                "infinite loop",
+                "molten lava",
+                "photons"
+            ]
+        );
+        assert_eq!(adventure.items.len(), 8);
+        let (checkpoint, _) = adventure.checkpoint.clone().unwrap();
+        assert_eq!(checkpoint, "Security Checkpoint");
+        let route = adventure.route(&adventure.start_room, &checkpoint).unwrap();
+        assert!(!route.is_empty());
+        let mut player = adventure.start.clone();
+        assert_eq!(
+            adventure.walk(&mut player, &adventure.start_room, &checkpoint),
+            Some(())
+        );
+        // Starting somewhere other than where the player is
+        let mut player = adventure.start.clone();
+        assert_eq!(
+            adventure.walk(&mut player, &checkpoint, &adventure.start_room),
+            None
+        );
+    }
+
+    #[test]
+    fn test_send_fault() {
+        // Prints a room then hits a bad opcode instead of asking for input
+        let room = "== Hull Breach ==\n";
+        let code = room.bytes().map(|b| format!("104,{},", b)).join("") + "98";
+        let (scene, waiting) = Player::new(Intcode::new(&code)).send("");
+        assert!(!waiting);
+        assert_eq!(
+            scene.room().map(|room| room.name.as_str()),
+            Some("Hull Breach")
+        );
+    }
+
+    #[test]
+    fn test_send_non_ascii() {
+        // A value outside the ASCII range in the middle of a room name
+        let code = "== Hull 1000Breach ==\n"
+            .split("1000")
+            .map(|text| text.bytes().map(|b| format!("104,{},", b)).join(""))
+            .join("104,1000,")
+            + "3,0";
+        let (scene, waiting) = Player::new(Intcode::new(&code)).send("");
+        assert!(waiting);
+        assert_eq!(
+            scene.room().map(|room| room.name.as_str()),
+            Some("Hull Breach")
+        );
+    }
+}
@@ -0,0 +1,117 @@
+// How add and mul deal with results that don't fit in an i64. Without this
+// they would panic in debug builds and silently wrap in release builds.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Arithmetic {
+    // Overflow is an error
+    #[default]
+    Checked,
+    Wrapping,
+    // Results too large for an i64 are kept at full precision (see
+    // Intcode::big_at), though input and output are still limited to i64
+    Big,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Arithmetic, InstructionSet, Intcode, IntcodeError, MemoryWatch, RunState};
+    use num::BigInt;
+
+    // Squares 2^40, then divides it back down by comparing against
+    // multiples of 2^40 (outputting the quotient, 2^40)
+    fn overflowing() -> Intcode {
+        let code = crate::assemble(
+            "
+                    mul #1099511627776, #1099511627776 -> [big]
+            loop:   mul [q], #1099511627776 -> [t]
+                    eq [t], [big] -> [done]
+                    jt [done], #found
+                    add [q], #1 -> [q]
+                    lt [q], #2000000000000 -> [done]
+                    jt [done], #loop
+                    hlt
+            found:  out [q]
+                    hlt
+            big:    db 0
+            q:      db 1099511627770
+            t:      db 0
+            done:   db 0
+            ",
+        )
+        .unwrap();
+        Intcode::new(&code)
+    }
+
+    #[test]
+    fn test_checked() {
+        let mut intcode = overflowing();
+        assert_eq!(
+            intcode.try_run(|| None, |_| {}),
+            Err(IntcodeError::Overflow { ptr: 0, relbase: 0 })
+        );
+        assert_eq!(intcode.ptr(), 0);
+    }
+
+    #[test]
+    fn test_wrapping() {
+        let mut intcode = overflowing();
+        intcode.set_arithmetic(Arithmetic::Wrapping);
+        assert_eq!(intcode.run_for(|| None, |_| {}, 1), RunState::StepLimit);
+        assert_eq!(intcode.code_at(30), 0); // 2^80 wraps to 0
+    }
+
+    #[test]
+    fn test_big() {
+        let mut intcode = overflowing();
+        intcode.set_arithmetic(Arithmetic::Big);
+        assert_eq!(intcode.run_simple::<i64>(&[]), Some(1099511627776));
+        assert_eq!(intcode.big_at(30), BigInt::from(2).pow(80));
+        assert!(intcode.is_halted());
+
+        // Outputs still have to fit
+        let mut intcode = Intcode::new("1102,4611686018427387904,4,7,4,7,99,0");
+        intcode.set_arithmetic(Arithmetic::Big);
+        assert_eq!(
+            intcode.try_run(|| None, |_| {}),
+            Err(IntcodeError::Overflow { ptr: 4, relbase: 0 })
+        );
+    }
+
+    #[test]
+    fn test_big_only_where_read() {
+        // Once 2^64 is stored, every instruction is still traced, and those
+        // not reading it are checked against the instruction set
+        let code = "1102,4294967296,4294967296,11,1001,12,1,12,4,12,99,0,0";
+        let mut intcode = Intcode::new(code);
+        intcode.set_arithmetic(Arithmetic::Big);
+        let mut watch = MemoryWatch::new(&intcode);
+        intcode.run_traced(|| None, |_| {}, &mut watch);
+        assert_eq!(intcode.big_at(11), BigInt::from(2).pow(64));
+        assert_eq!(watch.outputs().len(), 1);
+        assert_eq!(watch.steps(), 4);
+
+        let mut intcode = Intcode::new("1102,4294967296,4294967296,7,109,1,99,0");
+        intcode.set_arithmetic(Arithmetic::Big);
+        intcode.set_instruction_set(InstructionSet::day5());
+        assert_eq!(
+            intcode.try_run(|| None, |_| {}),
+            Err(IntcodeError::BadOpcode {
+                value: 109,
+                ptr: 4,
+                relbase: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_day9() {
+        for part in 1..=3 {
+            let code = crate::template::read_file_part("examples", crate::day!(9), part);
+            let expected = Intcode::new(&code).run_simplen::<i64>(&[]);
+            for arithmetic in [Arithmetic::Wrapping, Arithmetic::Big] {
+                let mut intcode = Intcode::new(&code);
+                intcode.set_arithmetic(arithmetic);
+                assert_eq!(intcode.run_simplen::<i64>(&[]), expected);
+            }
+        }
+    }
+}
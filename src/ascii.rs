@@ -0,0 +1,159 @@
+use crate::{Intcode, IntcodeError, RunState};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// A stretch of output from an ASCII-capable program: either text, or a
+// value outside the ASCII range (which is usually an answer)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AsciiOutput {
+    Text(String),
+    Value(i64),
+}
+
+impl fmt::Display for AsciiOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsciiOutput::Text(text) => write!(f, "{}", text),
+            AsciiOutput::Value(value) => writeln!(f, "[{}]", value),
+        }
+    }
+}
+
+pub fn split_ascii(output: &[i64]) -> Vec<AsciiOutput> {
+    let mut split = vec![];
+    for &value in output {
+        if !(0..128).contains(&value) {
+            split.push(AsciiOutput::Value(value));
+            continue;
+        }
+        let c = (value as u8) as char;
+        match split.last_mut() {
+            Some(AsciiOutput::Text(text)) => text.push(c),
+            _ => split.push(AsciiOutput::Text(c.to_string())),
+        }
+    }
+    split
+}
+
+// Drives an ASCII-capable program a line at a time, keeping the lines sent
+// and a transcript of the whole session (with values shown as [value])
+pub struct AsciiConsole {
+    pub intcode: Intcode,
+    history: Vec<String>,
+    transcript: String,
+}
+
+impl AsciiConsole {
+    pub fn new(intcode: Intcode) -> Self {
+        Self {
+            intcode,
+            history: vec![],
+            transcript: String::new(),
+        }
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    pub fn transcript(&self) -> &str {
+        &self.transcript
+    }
+
+    pub fn save_transcript(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, &self.transcript)
+    }
+
+    // Runs until the program wants more input (or stops or fails), returning
+    // what it output meanwhile
+    pub fn run(&mut self) -> (Vec<AsciiOutput>, Result<RunState, IntcodeError>) {
+        self.run_with(&[])
+    }
+
+    // Sends a line (without its newline) then runs as above
+    pub fn send(&mut self, line: &str) -> (Vec<AsciiOutput>, Result<RunState, IntcodeError>) {
+        self.history.push(line.to_string());
+        self.transcript.push_str(line);
+        self.transcript.push('\n');
+        let input: Vec<_> = line.chars().chain(['\n']).map(|c| c as i64).collect();
+        self.run_with(&input)
+    }
+
+    fn run_with(&mut self, input: &[i64]) -> (Vec<AsciiOutput>, Result<RunState, IntcodeError>) {
+        let mut input = input.iter().copied();
+        let mut output = vec![];
+        let state = self.intcode.try_run(|| input.next(), |v| output.push(v));
+        let output = split_ascii(&output);
+        for part in &output {
+            self.transcript.push_str(&part.to_string());
+        }
+        (output, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_ascii() {
+        assert_eq!(
+            split_ascii(&[72, 105, 10, 1000, -1, 33]),
+            [
+                AsciiOutput::Text("Hi\n".to_string()),
+                AsciiOutput::Value(1000),
+                AsciiOutput::Value(-1),
+                AsciiOutput::Text("!".to_string()),
+            ]
+        );
+        assert!(split_ascii(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_console() {
+        let code = crate::template::read_file("examples", crate::day!(21));
+        let mut console = AsciiConsole::new(Intcode::new(&code));
+        let (output, state) = console.run();
+        assert_eq!(
+            output,
+            [AsciiOutput::Text("Input instructions:\n".to_string())]
+        );
+        assert_eq!(state, Ok(RunState::NeedsInput));
+
+        let script = [
+            "NOT B J", "NOT C T", "OR T J", "AND D J", "NOT A T", "OR T J",
+        ];
+        for line in script {
+            assert_eq!(console.send(line), (vec![], Ok(RunState::NeedsInput)));
+        }
+        let (output, state) = console.send("WALK");
+        assert_eq!(output.last(), Some(&AsciiOutput::Value(19350375)));
+        assert_eq!(state, Ok(RunState::Halted));
+
+        assert_eq!(console.history().len(), 7);
+        assert!(console
+            .transcript()
+            .starts_with("Input instructions:\nNOT B J\n"));
+        assert!(console.transcript().ends_with("\n[19350375]\n"));
+    }
+
+    #[test]
+    fn test_console_fault() {
+        // Prints "ok" then hits a bad opcode
+        let mut console = AsciiConsole::new(Intcode::new("104,111,104,107,3,9,98,0,0,0"));
+        assert_eq!(console.run().1, Ok(RunState::NeedsInput));
+        let (output, state) = console.send("x");
+        assert!(output.is_empty());
+        assert_eq!(
+            state,
+            Err(IntcodeError::BadOpcode {
+                value: 98,
+                ptr: 6,
+                relbase: 0
+            })
+        );
+        assert_eq!(console.transcript(), "okx\n");
+    }
+}
@@ -0,0 +1,685 @@
+use crate::{Instruction, Mode, Op, Param};
+use hashbrown::HashMap;
+use itertools::Itertools;
+use std::fmt;
+
+// Assembles Intcode from a text syntax mirroring the disassembler listings:
+//
+//   ; comments run to the end of the line
+//   start:  in -> [n]                   ; labels end with a colon
+//   loop:   mul [acc], [n] -> [acc]     ; [addr] position, #value immediate
+//           add [n], #-1 -> [n]         ; rel[offset] relative
+//           jt [n], #loop               ; operands may be labels or label+N
+//           out [acc]
+//           hlt
+//   acc:    db 1                        ; data, including "strings" and 'c'haracters
+//   n:      ds 1                        ; reserve zeroed space
+//
+// An address followed by a colon (e.g. `0012:`) asserts the current address,
+// so that disassembler output can be fed straight back in. Macros are defined
+// with `macro name param, ...` / `endm`, refer to their parameters as `%param`
+// and can use `%%` for a number unique to each expansion (for local labels).
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl AsmError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+pub fn assemble(source: &str) -> Result<String, AsmError> {
+    Ok(assemble_code(source)?.iter().join(","))
+}
+
+pub fn assemble_code(source: &str) -> Result<Vec<i64>, AsmError> {
+    let mut macros = Macros::default();
+    let mut expanded = vec![];
+    let source: Vec<_> = source
+        .lines()
+        .enumerate()
+        .map(|(i, text)| (i + 1, text.to_string()))
+        .collect();
+    macros.expand(&source, &mut expanded, 0)?;
+
+    let lines = expanded
+        .iter()
+        .map(|(number, text)| Line::parse(*number, text))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // First pass: assign addresses to labels
+    let mut labels = HashMap::new();
+    let mut addr = 0;
+    for line in &lines {
+        if let Some(expected) = line.org {
+            if expected != addr {
+                return Err(AsmError::new(
+                    line.number,
+                    format!("expected address {} but assembling at {}", expected, addr),
+                ));
+            }
+        }
+        for label in &line.labels {
+            if labels.insert(label.clone(), addr as i64).is_some() {
+                return Err(AsmError::new(
+                    line.number,
+                    format!("duplicate label {:?}", label),
+                ));
+            }
+        }
+        addr += line.statement.as_ref().map_or(0, |s| s.len());
+    }
+
+    // Second pass: emit code
+    let mut code = Vec::with_capacity(addr);
+    for line in &lines {
+        let eval = |expr: &Expr| expr.eval(&labels, line.number);
+        match &line.statement {
+            None => {}
+            Some(Statement::Op {
+                instruction,
+                params,
+            }) => {
+                let params = params
+                    .iter()
+                    .map(|(mode, expr)| {
+                        Ok(Param {
+                            mode: *mode,
+                            value: eval(expr)?,
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                let op = Op {
+                    addr: code.len(),
+                    instruction: *instruction,
+                    params,
+                };
+                code.extend(op.encode());
+            }
+            Some(Statement::Data(items)) => {
+                for item in items {
+                    match item {
+                        DataItem::Expr(expr) => code.push(eval(expr)?),
+                        DataItem::Str(s) => code.extend(s.chars().map(|c| c as i64)),
+                    }
+                }
+            }
+            Some(Statement::Space(n)) => code.resize(code.len() + n, 0),
+        }
+    }
+
+    Ok(code)
+}
+
+#[derive(Default)]
+struct Macros {
+    defs: HashMap<String, Macro>,
+    expansions: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<(usize, String)>,
+}
+
+impl Macros {
+    const MAX_DEPTH: usize = 32;
+
+    fn expand(
+        &mut self,
+        lines: &[(usize, String)],
+        out: &mut Vec<(usize, String)>,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        let mut lines = lines.iter();
+        while let Some((number, text)) = lines.next() {
+            let text = strip_comment(text);
+            let (labels, rest) = split_labels(text);
+            let (word, args) = split_word(rest);
+
+            if word == "macro" {
+                let (name, params) = split_word(args);
+                if name.is_empty() || !labels.is_empty() {
+                    return Err(AsmError::new(*number, "expected `macro name param, ...`"));
+                }
+                let params = split_args(params)
+                    .into_iter()
+                    .map(|p| p.to_string())
+                    .collect();
+                let mut body = vec![];
+                loop {
+                    match lines.next() {
+                        Some((_, text)) if split_word(strip_comment(text)).0 == "endm" => break,
+                        Some(line) => body.push(line.clone()),
+                        None => {
+                            return Err(AsmError::new(
+                                *number,
+                                format!("unterminated macro {}", name),
+                            ))
+                        }
+                    }
+                }
+                self.defs.insert(name.to_string(), Macro { params, body });
+            } else if word == "endm" {
+                return Err(AsmError::new(*number, "endm without macro"));
+            } else if self.defs.contains_key(word) {
+                if depth >= Self::MAX_DEPTH {
+                    return Err(AsmError::new(
+                        *number,
+                        format!("macro {} nests too deeply", word),
+                    ));
+                }
+                let body = self.instantiate(word, split_args(args), *number)?;
+                if !labels.is_empty() {
+                    out.push((*number, labels.iter().map(|l| format!("{}:", l)).join(" ")));
+                }
+                self.expand(&body, out, depth + 1)?;
+            } else {
+                out.push((*number, text.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    fn instantiate(
+        &mut self,
+        name: &str,
+        args: Vec<&str>,
+        number: usize,
+    ) -> Result<Vec<(usize, String)>, AsmError> {
+        let def = &self.defs[name];
+        if args.len() != def.params.len() {
+            return Err(AsmError::new(
+                number,
+                format!(
+                    "macro {} takes {} argument(s) but {} given",
+                    name,
+                    def.params.len(),
+                    args.len()
+                ),
+            ));
+        }
+        self.expansions += 1;
+
+        // Substitute longest names first so `%ab` isn't clobbered by `%a`
+        let mut subs: Vec<_> = def.params.iter().zip(args).collect();
+        subs.sort_by_key(|(param, _)| std::cmp::Reverse(param.len()));
+        Ok(def
+            .body
+            .iter()
+            .map(|(_, text)| {
+                let mut text = text.replace("%%", &self.expansions.to_string());
+                for (param, arg) in &subs {
+                    text = text.replace(&format!("%{}", param), arg);
+                }
+                (number, text)
+            })
+            .collect())
+    }
+}
+
+struct Line {
+    number: usize,
+    org: Option<usize>,
+    labels: Vec<String>,
+    statement: Option<Statement>,
+}
+
+impl Line {
+    fn parse(number: usize, text: &str) -> Result<Self, AsmError> {
+        let err = |message: String| AsmError::new(number, message);
+
+        let (prefixes, rest) = split_labels(strip_comment(text));
+        let mut org = None;
+        let mut labels = vec![];
+        for prefix in prefixes {
+            if let Ok(addr) = prefix.parse() {
+                org = Some(addr);
+            } else {
+                labels.push(prefix.to_string());
+            }
+        }
+
+        let (word, args) = split_word(rest);
+        let statement = match word {
+            "" => None,
+            "db" => Some(Statement::Data(
+                split_args(args)
+                    .into_iter()
+                    .map(|arg| DataItem::parse(arg).map_err(err))
+                    .collect::<Result<_, _>>()?,
+            )),
+            "ds" => {
+                Some(Statement::Space(args.trim().parse().map_err(|_| {
+                    err(format!("invalid size {:?}", args.trim()))
+                })?))
+            }
+            _ => {
+                let instruction = Instruction::from_mnemonic(word)
+                    .ok_or_else(|| err(format!("unknown instruction {:?}", word)))?;
+                Some(Statement::parse_op(instruction, args).map_err(err)?)
+            }
+        };
+
+        Ok(Self {
+            number,
+            org,
+            labels,
+            statement,
+        })
+    }
+}
+
+enum Statement {
+    Op {
+        instruction: Instruction,
+        params: Vec<(Mode, Expr)>,
+    },
+    Data(Vec<DataItem>),
+    Space(usize),
+}
+
+impl Statement {
+    fn parse_op(instruction: Instruction, args: &str) -> Result<Self, String> {
+        let (sources, dest) = match args.split_once("->") {
+            Some((sources, dest)) => (sources, Some(dest.trim())),
+            None => (args, None),
+        };
+
+        let mut params = split_args(sources)
+            .into_iter()
+            .map(parse_operand)
+            .collect::<Result<Vec<_>, _>>()?;
+        let sources = instruction.arity() - instruction.writes() as usize;
+        if params.len() != sources {
+            return Err(format!(
+                "{} takes {} source operand(s) but {} given",
+                instruction.mnemonic(),
+                sources,
+                params.len()
+            ));
+        }
+
+        match (instruction.writes(), dest) {
+            (true, Some(dest)) => {
+                let dest = parse_operand(dest)?;
+                if dest.0 == Mode::Immediate {
+                    return Err(format!(
+                        "{} cannot write to an immediate",
+                        instruction.mnemonic()
+                    ));
+                }
+                params.push(dest);
+            }
+            (true, None) => {
+                return Err(format!(
+                    "{} requires a destination (-> ...)",
+                    instruction.mnemonic()
+                ))
+            }
+            (false, Some(_)) => {
+                return Err(format!(
+                    "{} does not take a destination",
+                    instruction.mnemonic()
+                ))
+            }
+            (false, None) => {}
+        }
+
+        Ok(Statement::Op {
+            instruction,
+            params,
+        })
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Statement::Op { params, .. } => 1 + params.len(),
+            Statement::Data(items) => items.iter().map(|item| item.len()).sum(),
+            Statement::Space(n) => *n,
+        }
+    }
+}
+
+enum DataItem {
+    Expr(Expr),
+    Str(String),
+}
+
+impl DataItem {
+    fn parse(arg: &str) -> Result<Self, String> {
+        match arg.strip_prefix('"') {
+            Some(quoted) => {
+                let body = quoted
+                    .strip_suffix('"')
+                    .ok_or_else(|| format!("unterminated string {}", arg))?;
+                Ok(DataItem::Str(unescape(body)?))
+            }
+            None => Ok(DataItem::Expr(Expr::parse(arg)?)),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            DataItem::Expr(_) => 1,
+            DataItem::Str(s) => s.chars().count(),
+        }
+    }
+}
+
+enum Term {
+    Number(i64),
+    Label(String),
+}
+
+// A sum of signed terms, e.g. `-3`, `loop`, `buffer+2`
+struct Expr(Vec<(i64, Term)>);
+
+impl Expr {
+    fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let invalid = || format!("invalid expression {:?}", text);
+        let mut terms = vec![];
+        let mut rest = text;
+        let mut sign = 1;
+        if let Some(r) = rest.strip_prefix('-') {
+            sign = -1;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('+') {
+            rest = r;
+        }
+        loop {
+            rest = rest.trim_start();
+            let end = if let Some(quoted) = rest.strip_prefix('\'') {
+                closing_quote(quoted, '\'')
+                    .map(|i| i + 2)
+                    .ok_or_else(invalid)?
+            } else {
+                rest.find(['+', '-']).unwrap_or(rest.len())
+            };
+            let term = rest[..end].trim();
+            let term = if let Some(c) = term.strip_prefix('\'') {
+                let c = unescape(c.strip_suffix('\'').ok_or_else(invalid)?)?;
+                let mut chars = c.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Term::Number(c as i64),
+                    _ => return Err(invalid()),
+                }
+            } else if let Ok(n) = term.parse() {
+                Term::Number(n)
+            } else if is_identifier(term) {
+                Term::Label(term.to_string())
+            } else {
+                return Err(invalid());
+            };
+            terms.push((sign, term));
+
+            rest = rest[end..].trim_start();
+            sign = match rest.chars().next() {
+                None => break,
+                Some('+') => 1,
+                Some('-') => -1,
+                Some(_) => return Err(invalid()),
+            };
+            rest = &rest[1..];
+        }
+        Ok(Self(terms))
+    }
+
+    fn eval(&self, labels: &HashMap<String, i64>, number: usize) -> Result<i64, AsmError> {
+        self.0.iter().try_fold(0_i64, |sum, (sign, term)| {
+            let value = match term {
+                Term::Number(n) => *n,
+                Term::Label(label) => *labels
+                    .get(label)
+                    .ok_or_else(|| AsmError::new(number, format!("unknown label {:?}", label)))?,
+            };
+            sign.checked_mul(value)
+                .and_then(|value| sum.checked_add(value))
+                .ok_or_else(|| AsmError::new(number, "expression overflows"))
+        })
+    }
+}
+
+fn parse_operand(arg: &str) -> Result<(Mode, Expr), String> {
+    let arg = arg.trim();
+    if let Some(expr) = arg.strip_prefix('#') {
+        Ok((Mode::Immediate, Expr::parse(expr)?))
+    } else if let Some(expr) = arg.strip_prefix("rel[").and_then(|a| a.strip_suffix(']')) {
+        Ok((Mode::Relative, Expr::parse(expr)?))
+    } else if let Some(expr) = arg.strip_prefix('[').and_then(|a| a.strip_suffix(']')) {
+        Ok((Mode::Position, Expr::parse(expr)?))
+    } else {
+        Err(format!(
+            "invalid operand {:?} (expected #value, [addr] or rel[offset])",
+            arg
+        ))
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn unescape(s: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        result.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some(c @ ('\\' | '"' | '\'')) => c,
+            c => return Err(format!("invalid escape \\{}", c.unwrap_or(' '))),
+        });
+    }
+    Ok(result)
+}
+
+// Where a quote closes in text following the opening quote, skipping
+// escaped characters
+fn closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == quote => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &text[..i],
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), _) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    text
+}
+
+// Splits leading `label:` (or `0012:`) prefixes from the rest of a line
+fn split_labels(text: &str) -> (Vec<&str>, &str) {
+    let mut labels = vec![];
+    let mut rest = text.trim();
+    while let Some((label, after)) = rest.split_once(':') {
+        let label = label.trim();
+        if !is_identifier(label) && label.parse::<usize>().is_err() {
+            break;
+        }
+        labels.push(label);
+        rest = after.trim();
+    }
+    (labels, rest)
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+// Splits on commas that aren't inside a string or character literal
+fn split_args(text: &str) -> Vec<&str> {
+    let text = text.trim();
+    if text.is_empty() {
+        return vec![];
+    }
+    let mut args = vec![];
+    let mut quote = None;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ',') => {
+                args.push(text[start..i].trim());
+                start = i + 1;
+            }
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), _) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    args.push(text[start..].trim());
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble_operands() {
+        assert_eq!(
+            assemble("add [10], #3 -> rel[+2]\nin -> [5]\nout #-7\narb rel[-1]\nhlt"),
+            Ok("21001,10,3,2,3,5,104,-7,209,-1,99".to_string())
+        );
+    }
+
+    #[test]
+    fn test_assemble_labels_and_data() {
+        let source = "
+            start:  jt #1, #main    ; skip over the data
+            msg:    db \"Hi\\n\", 0
+            n:      ds 2
+            main:   out [msg+1]
+                    hlt
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok("1105,1,9,72,105,10,0,0,0,4,4,99".to_string())
+        );
+    }
+
+    #[test]
+    fn test_assemble_macros() {
+        let source = "
+            macro push value
+                add %value, #0 -> rel[+0]
+                arb #1
+            endm
+            macro count from
+                add #0, %from -> [n]
+            loop%%: out [n]
+                add [n], #-1 -> [n]
+                jt [n], #loop%%
+            endm
+                    push #'A'
+            twice:  count #2
+                    count #1
+                    hlt
+            n:      db 0
+        ";
+        let code = assemble_code(source).unwrap();
+        assert_eq!(&code[..6], [21101, 65, 0, 0, 109, 1]);
+        assert_eq!(code.len(), 6 + 2 * 13 + 2);
+    }
+
+    #[test]
+    fn test_assemble_address_assertions() {
+        assert_eq!(
+            assemble("0000: out #1\n0002: hlt"),
+            Ok("104,1,99".to_string())
+        );
+        assert_eq!(
+            assemble("0000: out #1\n0003: hlt"),
+            Err(AsmError::new(2, "expected address 3 but assembling at 2"))
+        );
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert_eq!(
+            assemble("jt [x], #nowhere\nx: db 0").unwrap_err(),
+            AsmError::new(1, "unknown label \"nowhere\"")
+        );
+        assert_eq!(
+            assemble("hlt\nadd #1, #2 -> #3").unwrap_err(),
+            AsmError::new(2, "add cannot write to an immediate")
+        );
+        assert_eq!(
+            assemble("nop").unwrap_err(),
+            AsmError::new(1, "unknown instruction \"nop\"")
+        );
+        assert_eq!(
+            assemble("db 9223372036854775807 + 1").unwrap_err(),
+            AsmError::new(1, "expression overflows")
+        );
+        assert_eq!(
+            assemble("db -2 - 9223372036854775807").unwrap_err(),
+            AsmError::new(1, "expression overflows")
+        );
+    }
+
+    #[test]
+    fn test_assemble_escaped_quotes() {
+        assert_eq!(
+            assemble(r#"db '\'', '\\' + 1, "a\\", ';' ; comment"#),
+            Ok("39,93,97,92,59".to_string())
+        );
+    }
+
+    #[test]
+    fn test_assemble_disassembly() {
+        let code = crate::template::read_file("examples", crate::day!(25));
+        let intcode = crate::Intcode::new(&code);
+        let listing = intcode.disassemble().to_string();
+        assert_eq!(assemble(&listing), Ok(code.trim().to_string()));
+    }
+}
@@ -2,9 +2,6 @@
 use advent_of_code::*;
 use itertools::Itertools;
 use rayon::prelude::*;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
-use std::thread;
 
 advent_of_code::solution!(7);
 
@@ -17,74 +14,32 @@ pub fn part_two(input: &str) -> Option<i64> {
 }
 
 fn find_max(input: &str, s: i64, e: i64) -> Option<i64> {
+    let mut intcode = Intcode::new(input);
+    intcode.compile();
     let to_consider: Vec<_> = (s..=e).permutations((e - s + 1) as usize).collect();
     to_consider
         .par_iter()
-        .map(|phase_seq| run_amps(input, phase_seq))
+        .map(|phase_seq| run_amps(&intcode, phase_seq))
         .max()
 }
 
-fn run_amps(codestr: &str, phases: &[i64]) -> i64 {
-    Amp::chained(codestr, phases)
-        .into_iter()
-        .map(|mut amp| thread::spawn(move || amp.run()))
-        .collect::<Vec<_>>()
-        .into_iter()
+// Each amplifier's output feeds the next one's input, with the last one's
+// output (the thrust) fed back into the first
+fn run_amps(intcode: &Intcode, phases: &[i64]) -> i64 {
+    let mut network = Network::new(intcode, phases.len(), Topology::Ring);
+    for (addr, phase) in phases.iter().enumerate() {
+        network.send(addr, &[*phase]);
+    }
+    network.send(0, &[0]);
+    network.run(|_| false);
+    let last = phases.len() as i64 - 1;
+    network
+        .log()
+        .iter()
         .rev()
-        .map(|thread| thread.join().unwrap())
-        .last()
-        .unwrap()
+        .find(|packet| packet.from == last)
         .unwrap()
-}
-
-struct Amp {
-    intcode: Intcode,
-    input: Receiver<i64>,
-    output: Sender<i64>,
-}
-
-impl Amp {
-    fn chained(input: &str, phases: &[i64]) -> Vec<Self> {
-        let mut result = Vec::<Self>::with_capacity(phases.len());
-        for (pos, phase) in phases.iter().enumerate() {
-            let t = if pos == 0 {
-                Self::new(input, *phase)
-            } else {
-                result[pos - 1].chain(*phase)
-            };
-            result.push(t);
-        }
-        result
-    }
-
-    fn new(input: &str, phase: i64) -> Self {
-        let intcode = Intcode::new(input);
-        let (output, input): (Sender<i64>, Receiver<i64>) = mpsc::channel();
-        output.send(phase).unwrap();
-        output.send(0).unwrap();
-        Self {
-            intcode,
-            input,
-            output,
-        }
-    }
-
-    fn chain(&mut self, phase: i64) -> Self {
-        let intcode = self.intcode.clone();
-        let (prev_output, input): (Sender<i64>, Receiver<i64>) = mpsc::channel();
-        prev_output.send(phase).unwrap();
-        let output = std::mem::replace(&mut self.output, prev_output);
-        Self {
-            intcode,
-            input,
-            output,
-        }
-    }
-
-    fn run(&mut self) -> Option<i64> {
-        self.intcode.run_async(&self.input, &self.output);
-        self.input.recv().ok()
-    }
+        .payload[0]
 }
 
 #[cfg(test)]
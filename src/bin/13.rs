@@ -97,6 +97,7 @@ impl IntcodeIO for Arcade {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::RefCell;
 
     #[test]
     fn test_part_one() {
@@ -109,4 +110,47 @@ mod tests {
         let result = part_two(&advent_of_code::template::read_file("examples", DAY));
         assert_eq!(result, Some(10247));
     }
+
+    #[test]
+    fn test_replay() {
+        let input = advent_of_code::template::read_file("examples", DAY);
+        let mut intcode = Intcode::new(&input);
+        intcode.set_code(0, 2);
+        let mut recorder = Recorder::new(Arcade::new());
+        recorder.run(&mut intcode.clone());
+        assert_eq!(recorder.io().score, 10247);
+        assert_eq!(
+            recorder.session().replay(&mut intcode),
+            Ok(RunState::Halted)
+        );
+    }
+
+    #[test]
+    fn test_score_address() {
+        let input = advent_of_code::template::read_file("examples", DAY);
+        let mut intcode = Intcode::new(&input);
+        intcode.set_code(0, 2);
+        let arcade = RefCell::new(Arcade::new());
+        let mut watch = MemoryWatch::new(&intcode);
+        intcode.run_traced(
+            || arcade.borrow_mut().input(),
+            |value| arcade.borrow_mut().output(value),
+            &mut watch,
+        );
+
+        // Scores are drawn at (-1, 0)
+        let scores = |output: &[i64]| {
+            output.len() % 3 == 0 && output[output.len() - 3..output.len() - 1] == [-1, 0]
+        };
+        let (addr, _) = watch.correlate(scores)[0];
+        assert_eq!(intcode.code_at(addr), 10247);
+
+        // Starting with points already on the board
+        let mut intcode = Intcode::new(&input);
+        intcode.set_code(0, 2);
+        intcode.set_code(addr, 1000);
+        let mut arcade = Arcade::new();
+        intcode.run_with_io(&mut arcade);
+        assert_eq!(arcade.score, 11247);
+    }
 }
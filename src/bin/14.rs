@@ -47,7 +47,7 @@ impl Reactions {
             .into_iter()
             .map(|(prereqs, result)| Reaction::new(prereqs, result, &elements))
             .collect();
-        reactions.sort_by(|a, b| b.element.cmp(&a.element));
+        reactions.sort_by_key(|r| std::cmp::Reverse(r.element));
 
         Self {
             elements: elements.len(),
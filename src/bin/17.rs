@@ -1,8 +1,7 @@
 #[allow(unused_imports)]
 use advent_of_code::*;
-use fancy_regex::Regex;
 use hashbrown::HashSet;
-use itertools::Itertools;
+use std::fmt;
 
 advent_of_code::solution!(17);
 
@@ -12,13 +11,34 @@ pub fn part_one(input: &str) -> Option<isize> {
 }
 
 pub fn part_two(input: &str) -> Option<i64> {
-    let mut map = Map::from_intcode(input);
-    let path = map.traverse();
-    let program = compile_program(path, 20);
+    let map = Map::from_intcode(input);
+    let compressor = Compressor::new(3, 20);
+    let routine = map.find_route(&mut |route| compressor.first(route))?;
 
     let mut intcode = Intcode::new(input);
     intcode.set_code(0, 2);
-    intcode.run_ascii(&program)
+    intcode.run_ascii(&format!("{}n\n", routine.program(3)))
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Move {
+    Left,
+    Right,
+    Forward(usize),
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Move::Left => write!(f, "L"),
+            Move::Right => write!(f, "R"),
+            Move::Forward(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+fn edge(a: Pos, b: Pos) -> (Pos, Pos) {
+    (a.min(b), a.max(b))
 }
 
 #[derive(Default)]
@@ -78,72 +98,75 @@ impl Map {
                 .is_some()
     }
 
-    fn traverse(&mut self) -> String {
-        let mut path = String::from("");
-        let mut dist = 0;
-        loop {
-            let forward_pos = self.dir.forward_from(&self.pos);
-            let left_dir = self.dir.turn_left();
-            let right_dir = self.dir.turn_right();
-            if self.scaffold.contains(&forward_pos) {
-                self.pos = forward_pos;
-                dist += 1;
-            } else if self.scaffold.contains(&left_dir.forward_from(&self.pos)) {
-                if dist > 0 {
-                    path.push_str(&format!("{},", dist));
-                }
-                path.push_str("L,");
-                self.dir = left_dir;
-                dist = 0;
-            } else if self.scaffold.contains(&right_dir.forward_from(&self.pos)) {
-                if dist > 0 {
-                    path.push_str(&format!("{},", dist));
-                }
-                path.push_str("R,");
-                self.dir = right_dir;
-                dist = 0;
-            } else {
-                break;
+    // Tries routes along every bit of scaffold, going straight on at
+    // intersections before turning, until the visitor makes something of one
+    fn find_route<R, F>(&self, visit: &mut F) -> Option<R>
+    where
+        F: FnMut(&[Move]) -> Option<R>,
+    {
+        let edges = self
+            .scaffold
+            .iter()
+            .flat_map(|pos| DIRECTIONS.iter().map(move |dir| dir.forward_from(pos)))
+            .filter(|pos| self.scaffold.contains(pos))
+            .count()
+            / 2;
+        let mut used = HashSet::new();
+        self.route_from(self.pos, self.dir, edges, &mut used, &mut vec![], visit)
+    }
+
+    fn route_from<R, F>(
+        &self,
+        pos: Pos,
+        dir: Direction,
+        edges: usize,
+        used: &mut HashSet<(Pos, Pos)>,
+        route: &mut Vec<Move>,
+        visit: &mut F,
+    ) -> Option<R>
+    where
+        F: FnMut(&[Move]) -> Option<R>,
+    {
+        let options = [
+            (None, dir),
+            (Some(Move::Left), dir.turn_left()),
+            (Some(Move::Right), dir.turn_right()),
+        ];
+        let mut stuck = true;
+        for (turn, dir) in options {
+            let next = dir.forward_from(&pos);
+            if !self.scaffold.contains(&next) || used.contains(&edge(pos, next)) {
+                continue;
+            }
+            stuck = false;
+
+            let len = route.len();
+            let further = turn.is_none() && matches!(route.last(), Some(Move::Forward(_)));
+            match route.last_mut() {
+                Some(Move::Forward(n)) if further => *n += 1,
+                _ => route.extend([turn, Some(Move::Forward(1))].into_iter().flatten()),
+            }
+            used.insert(edge(pos, next));
+            let found = self.route_from(next, dir, edges, used, route, visit);
+            used.remove(&edge(pos, next));
+            match route.last_mut() {
+                Some(Move::Forward(n)) if further => *n -= 1,
+                _ => route.truncate(len),
+            }
+            if found.is_some() {
+                return found;
             }
         }
-        if dist > 0 {
-            path.push_str(&format!("{},", dist));
-        }
-        path
+        (stuck && used.len() == edges)
+            .then(|| visit(route))
+            .flatten()
     }
 }
 
-fn compile_program(code: String, limit: usize) -> String {
-    let abc = format!("(.{{1,{}}},)", limit - 1);
-    let re = Regex::new(&format!(
-        r"^{}\1*{}(?:\1|\2)*{}(?:\1|\2|\3)*$",
-        &abc, &abc, &abc
-    ))
-    .unwrap();
-    let captures = re
-        .captures(&code)
-        .expect("Error running regex")
-        .expect("No match found");
-
-    // Sort the groups so that the longest gets replaced first (so we don't get unlucky)
-    let mut groups: Vec<_> = (1..=3)
-        .map(|g| captures.get(g).unwrap().as_str().trim_end_matches(","))
-        .collect();
-    groups.sort_by_key(|b| std::cmp::Reverse(b.len()));
-    let (a, b, c) = groups.iter().tuples().next().unwrap();
-
-    let code = code
-        .replacen(a, "A", limit)
-        .replacen(b, "B", limit)
-        .replacen(c, "C", limit);
-    let main = code.trim_end_matches(",");
-
-    format!("{}\n{}\n{}\n{}\nn\n", main, a, b, c)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use itertools::Itertools;
 
     #[test]
     fn test_part_one_sample() {
@@ -169,7 +192,7 @@ mod tests {
 
     #[test]
     fn test_part_two_sample() {
-        let mut map = Map::from_str(
+        let map = Map::from_str(
             r#"
 #######...#####
 #.....#...#...#
@@ -188,19 +211,29 @@ mod tests {
 ....#####......
         "#,
         );
-        let path = map.traverse();
+        let mut routes = vec![];
+        map.find_route(&mut |route| {
+            routes.push(route.iter().join(","));
+            None::<()>
+        });
         assert_eq!(
-            &path,
-            "R,8,R,8,R,4,R,4,R,8,L,6,L,2,R,4,R,4,R,8,R,8,R,8,L,6,L,2,"
+            routes[0],
+            "R,8,R,8,R,4,R,4,R,8,L,6,L,2,R,4,R,4,R,8,R,8,R,8,L,6,L,2"
         );
-        let program = compile_program(path, 12);
+        // Turning at some of the intersections instead
+        assert_eq!(routes.len(), 16);
+        assert_eq!(routes.iter().unique().count(), 16);
+
+        let compressor = Compressor::new(3, 12);
+        let routine = map
+            .find_route(&mut |route| compressor.first(route))
+            .unwrap();
         assert_eq!(
-            &program,
-            r#"B,A,C,A,B,C
-R,4,R,4,R,8
+            routine.program(3),
+            r#"A,B,C,B,A,C
 R,8,R,8
+R,4,R,4,R,8
 L,6,L,2
-n
 "#
         );
     }
@@ -31,7 +31,9 @@ struct Drone(Intcode);
 
 impl Drone {
     fn new(input: &str) -> Self {
-        Self(Intcode::new(input))
+        let mut intcode = Intcode::new(input);
+        intcode.compile();
+        Self(intcode)
     }
 
     fn scan_row(&mut self, y: isize) -> Option<(isize, isize)> {
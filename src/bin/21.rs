@@ -1,44 +1,20 @@
 #[allow(unused_imports)]
 use advent_of_code::*;
-use unindent::unindent;
 
 advent_of_code::solution!(21);
 
 pub fn part_one(input: &str) -> Option<i64> {
-    // ((!b || !c) && d) || !a
-    run(
-        input,
-        "
-            NOT B J
-            NOT C T
-            OR T J
-            AND D J
-            NOT A T
-            OR T J
-            WALK
-        ",
-    )
+    run(input, SpringMode::Walk)
 }
 
 pub fn part_two(input: &str) -> Option<i64> {
-    // ((!b || !c) && d && h) || !a
-    run(
-        input,
-        "
-            NOT B J
-            NOT C T
-            OR T J
-            AND D J
-            AND H J
-            NOT A T
-            OR T J
-            RUN
-        ",
-    )
+    run(input, SpringMode::Run)
 }
 
-fn run(input: &str, program: &str) -> Option<i64> {
-    Intcode::new(input).run_ascii(&unindent(program))
+// Learns when to jump from the hulls the droid falls into
+fn run(input: &str, mode: SpringMode) -> Option<i64> {
+    let mut learner = SpringLearner::new(Intcode::new(input), mode);
+    learner.solve().ok().map(|(_, damage)| damage)
 }
 
 #[cfg(test)]
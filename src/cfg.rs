@@ -0,0 +1,367 @@
+use crate::{reachable_ops_from, write_graph, Instruction, Mode, Op};
+use petgraph::dot::Dot;
+use petgraph::graph::NodeIndex;
+use petgraph::Graph;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+// How control leaves a basic block. Calls are a push of the return address
+// onto the stack followed by an unconditional jump, and returns are an
+// unconditional jump through a stack slot (e.g. `jt #1, rel[+0]`).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Exit {
+    Next(usize),
+    Jump(usize),
+    // Taken target is None when it isn't statically known
+    Branch { taken: Option<usize>, next: usize },
+    // Target is None for calls through a function pointer
+    Call { target: Option<usize>, ret: usize },
+    Return,
+    Indirect,
+    Halt,
+}
+
+impl Exit {
+    // Successors within the same function (a call continues at its return)
+    pub fn successors(&self) -> Vec<usize> {
+        match *self {
+            Exit::Next(addr) | Exit::Jump(addr) => vec![addr],
+            Exit::Branch { taken, next } => taken.into_iter().chain([next]).collect(),
+            Exit::Call { ret, .. } => vec![ret],
+            Exit::Return | Exit::Indirect | Exit::Halt => vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub ops: Vec<Op>,
+    pub exit: Exit,
+}
+
+impl Block {
+    // Address following the last instruction
+    pub fn end(&self) -> usize {
+        self.ops.last().map_or(self.start, |op| op.addr + op.len())
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, op) in self.ops.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:04}: {}", op.addr, op)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Function {
+    pub entry: usize,
+    // Size of the stack frame allocated on entry (by `arb #n`)
+    pub frame: i64,
+    // Start addresses of the blocks making up the function
+    pub blocks: BTreeSet<usize>,
+    // Entry addresses of statically known callees
+    pub calls: BTreeSet<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Cfg {
+    pub blocks: BTreeMap<usize, Block>,
+    pub functions: BTreeMap<usize, Function>,
+}
+
+impl Cfg {
+    pub fn new(code: &[i64]) -> Self {
+        let ops = find_ops(code);
+
+        let mut leaders = BTreeSet::from([0]);
+        let mut entries = BTreeSet::from([0]);
+        for (addr, op) in &ops {
+            if matches!(
+                op.instruction,
+                Instruction::JumpIfTrue | Instruction::JumpIfFalse | Instruction::Halt
+            ) {
+                leaders.insert(addr + op.len());
+            }
+            leaders.extend(op.jump_target());
+            entries
+                .extend(function_pointer(op).filter(|addr| ops.get(addr).is_some_and(is_prologue)));
+        }
+        leaders.extend(&entries);
+
+        let mut blocks = BTreeMap::new();
+        let mut current: Option<Block> = None;
+        for (addr, op) in &ops {
+            if let Some(block) = current.take() {
+                if leaders.contains(addr) || block.end() != *addr {
+                    blocks.insert(block.start, block);
+                } else {
+                    current = Some(block);
+                }
+            }
+            let block = current.get_or_insert_with(|| Block {
+                start: *addr,
+                ops: vec![],
+                exit: Exit::Next(*addr),
+            });
+            block.ops.push(op.clone());
+            block.exit = exit(&block.ops);
+            if !matches!(block.exit, Exit::Next(_)) {
+                blocks.insert(block.start, current.take().unwrap());
+            }
+        }
+        if let Some(block) = current {
+            blocks.insert(block.start, block);
+        }
+
+        for block in blocks.values() {
+            if let Exit::Call {
+                target: Some(target),
+                ..
+            } = block.exit
+            {
+                entries.insert(target);
+            }
+        }
+        let functions = entries
+            .iter()
+            .filter(|entry| blocks.contains_key(entry))
+            .map(|&entry| (entry, function(&blocks, &ops, entry)))
+            .collect();
+
+        Self { blocks, functions }
+    }
+
+    // Function containing the given block (the one with the lowest entry if
+    // the block is shared)
+    pub fn function_of(&self, block: usize) -> Option<&Function> {
+        self.functions.values().find(|f| f.blocks.contains(&block))
+    }
+
+    // Blocks as nodes, connected by control flow. Call edges go to the
+    // callee's entry, with a "ret" edge to where the call returns.
+    pub fn graph(&self) -> Graph<String, &'static str> {
+        let mut graph = Graph::new();
+        let nodes: BTreeMap<usize, NodeIndex> = self
+            .blocks
+            .iter()
+            .map(|(start, block)| {
+                let label = match self.functions.get(start) {
+                    Some(_) => format!("fn_{:04}:\n{}", start, block),
+                    None => block.to_string(),
+                };
+                (*start, graph.add_node(label))
+            })
+            .collect();
+        let mut edge = |from: usize, to: Option<usize>, label| {
+            if let Some(to) = to.and_then(|to| nodes.get(&to)) {
+                graph.add_edge(nodes[&from], *to, label);
+            }
+        };
+        for (start, block) in &self.blocks {
+            match block.exit {
+                Exit::Next(next) => edge(*start, Some(next), ""),
+                Exit::Jump(target) => edge(*start, Some(target), "jump"),
+                Exit::Branch { taken, next } => {
+                    edge(*start, taken, "taken");
+                    edge(*start, Some(next), "else");
+                }
+                Exit::Call { target, ret } => {
+                    edge(*start, target, "call");
+                    edge(*start, Some(ret), "ret");
+                }
+                Exit::Return | Exit::Indirect | Exit::Halt => {}
+            }
+        }
+        graph
+    }
+
+    pub fn dot(&self) -> String {
+        Dot::new(&self.graph()).to_string()
+    }
+
+    // Renders through graphviz, with the format taken from the extension
+    pub fn write_graph(&self, filename: &str) {
+        write_graph(&self.graph(), filename);
+    }
+}
+
+// Reachable code, including functions only ever called through pointers
+fn find_ops(code: &[i64]) -> BTreeMap<usize, Op> {
+    let mut roots = vec![0];
+    loop {
+        let ops = reachable_ops_from(code, &roots);
+        let pointers: Vec<_> = ops
+            .values()
+            .filter_map(function_pointer)
+            .filter(|addr| !ops.contains_key(addr) && !roots.contains(addr))
+            .filter(|addr| Op::decode(code, *addr).is_some_and(|op| is_prologue(&op)))
+            .collect();
+        if pointers.is_empty() {
+            return ops;
+        }
+        roots.extend(pointers);
+    }
+}
+
+fn is_prologue(op: &Op) -> bool {
+    op.instruction == Instruction::ShiftBase
+        && op.params[0].mode == Mode::Immediate
+        && op.params[0].value > 0
+}
+
+// Constant stored by `add #a, #b -> ..` (or mul), which if it's the address
+// of a function prologue is (probably) a function pointer
+fn function_pointer(op: &Op) -> Option<usize> {
+    match (op.instruction, &op.params[..]) {
+        (Instruction::Add | Instruction::Multiply, [a, b, _])
+            if a.mode == Mode::Immediate && b.mode == Mode::Immediate =>
+        {
+            let value = if op.instruction == Instruction::Add {
+                a.value.checked_add(b.value)
+            } else {
+                a.value.checked_mul(b.value)
+            };
+            usize::try_from(value?).ok()
+        }
+        _ => None,
+    }
+}
+
+fn exit(ops: &[Op]) -> Exit {
+    let op = ops.last().unwrap();
+    let next = op.addr + op.len();
+    match op.instruction {
+        Instruction::Halt => Exit::Halt,
+        Instruction::JumpIfTrue | Instruction::JumpIfFalse => match op.jump_always() {
+            Some(false) => Exit::Next(next),
+            None => Exit::Branch {
+                taken: op.jump_target(),
+                next,
+            },
+            Some(true) => {
+                let pushed = ops
+                    .len()
+                    .checked_sub(2)
+                    .and_then(|i| ops[i].pushed_constant());
+                if pushed == Some(next as i64) {
+                    Exit::Call {
+                        target: op.jump_target(),
+                        ret: next,
+                    }
+                } else if let Some(target) = op.jump_target() {
+                    Exit::Jump(target)
+                } else if op.params[1].mode == Mode::Relative {
+                    Exit::Return
+                } else {
+                    Exit::Indirect
+                }
+            }
+        },
+        _ => Exit::Next(next),
+    }
+}
+
+fn function(blocks: &BTreeMap<usize, Block>, ops: &BTreeMap<usize, Op>, entry: usize) -> Function {
+    let mut function = Function {
+        entry,
+        frame: ops
+            .get(&entry)
+            .filter(|op| is_prologue(op))
+            .map_or(0, |op| op.params[0].value),
+        ..Default::default()
+    };
+    let mut todo = vec![entry];
+    while let Some(start) = todo.pop() {
+        let Some(block) = blocks.get(&start) else {
+            continue;
+        };
+        if !function.blocks.insert(start) {
+            continue;
+        }
+        if let Exit::Call {
+            target: Some(target),
+            ..
+        } = block.exit
+        {
+            function.calls.insert(target);
+        }
+        todo.extend(block.exit.successors());
+    }
+    function
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_code;
+
+    #[test]
+    fn test_blocks() {
+        // Outputs double its input, using a function
+        let code = assemble_code(
+            "
+                    arb #100
+                    in -> rel[+1]
+                    add #ret, #0 -> rel[+0]
+                    jt #1, #double
+            ret:    out rel[+1]
+                    hlt
+            double: arb #2
+                    jf rel[-1], #done
+                    mul rel[-1], #2 -> rel[-1]
+            done:   arb #-2
+                    jt #1, rel[+0]
+            ",
+        )
+        .unwrap();
+        let cfg = Cfg::new(&code);
+        let exits: Vec<_> = cfg.blocks.values().map(|b| (b.start, b.exit)).collect();
+        assert_eq!(
+            exits,
+            [
+                (
+                    0,
+                    Exit::Call {
+                        target: Some(14),
+                        ret: 11
+                    }
+                ),
+                (11, Exit::Halt),
+                (
+                    14,
+                    Exit::Branch {
+                        taken: Some(23),
+                        next: 19
+                    }
+                ),
+                (19, Exit::Next(23)),
+                (23, Exit::Return),
+            ]
+        );
+        assert_eq!(cfg.functions.keys().collect::<Vec<_>>(), [&0, &14]);
+        let double = &cfg.functions[&14];
+        assert_eq!(double.frame, 2);
+        assert_eq!(double.blocks, BTreeSet::from([14, 19, 23]));
+        assert_eq!(cfg.functions[&0].calls, BTreeSet::from([14]));
+        assert!(cfg.dot().contains("label = \"call\""));
+    }
+
+    #[test]
+    fn test_day25() {
+        let code = crate::template::read_file("examples", crate::day!(25));
+        let cfg = Cfg::new(&crate::Intcode::new(&code).memory().to_vec());
+        // Called directly, and only through a pointer (passed at 1240)
+        for entry in [1130, 1174, 1234, 1256, 1310, 1424] {
+            assert!(cfg.functions.contains_key(&entry), "{}", entry);
+        }
+        assert_eq!(cfg.functions[&1234].calls, BTreeSet::from([1174]));
+        assert_eq!(cfg.blocks[&1251].exit, Exit::Return);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{reachable_ops, Instruction, Mode, Op};
+
+// An operand with its mode already resolved
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Arg {
+    Immediate(i64),
+    Position(usize),
+    Relative(i64),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct CompiledOp {
+    pub(crate) instruction: Instruction,
+    pub(crate) args: [Arg; 3],
+    pub(crate) len: usize,
+}
+
+impl CompiledOp {
+    fn new(op: &Op) -> Option<Self> {
+        let mut args = [Arg::Immediate(0); 3];
+        for (arg, param) in args.iter_mut().zip(&op.params) {
+            *arg = match param.mode {
+                Mode::Immediate => Arg::Immediate(param.value),
+                // Negative addresses are left to the interpreter to report
+                Mode::Position => Arg::Position(usize::try_from(param.value).ok()?),
+                Mode::Relative => Arg::Relative(param.value),
+            };
+        }
+        Some(Self {
+            instruction: op.instruction,
+            args,
+            len: op.len(),
+        })
+    }
+}
+
+// Pre-decoded form of every statically reachable instruction in a program.
+// This is shared (read only) between clones of a machine; each machine
+// tracks which of these it has since overwritten and interprets those
+// instead.
+#[derive(Debug, Default)]
+pub struct Compiled {
+    ops: Vec<Option<CompiledOp>>,
+    // Start address of the compiled op covering each address
+    owners: Vec<Option<usize>>,
+}
+
+impl Compiled {
+    pub fn new(code: &[i64]) -> Self {
+        Self::filtered(code, |_| true)
+    }
+
+    // Only compiles the reachable instructions matching the filter
+    pub fn filtered(code: &[i64], filter: impl Fn(&Op) -> bool) -> Self {
+        let mut compiled = Self {
+            ops: vec![None; code.len()],
+            owners: vec![None; code.len()],
+        };
+        for (addr, op) in reachable_ops(code).into_iter().filter(|(_, op)| filter(op)) {
+            // Ops running off the end read parameters that may be written
+            // later, without them being marked stale, so aren't compiled
+            if addr + op.len() > code.len() {
+                continue;
+            }
+            let Some(compiled_op) = CompiledOp::new(&op) else {
+                continue;
+            };
+            compiled.ops[addr] = Some(compiled_op);
+            for owner in &mut compiled.owners[addr..addr + op.len()] {
+                *owner = Some(addr);
+            }
+        }
+        compiled
+    }
+
+    // Number of instructions that were compiled
+    pub fn ops(&self) -> usize {
+        self.ops.iter().flatten().count()
+    }
+
+    #[inline]
+    pub(crate) fn op(&self, addr: usize) -> Option<CompiledOp> {
+        self.ops.get(addr).copied().flatten()
+    }
+
+    #[inline]
+    pub(crate) fn owner(&self, addr: usize) -> Option<usize> {
+        self.owners.get(addr).copied().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Intcode;
+
+    #[test]
+    fn test_compile() {
+        let compiled = Compiled::new(&[1101, 2, 3, 7, 4, 7, 99, 0]);
+        assert_eq!(compiled.ops(), 3);
+        assert_eq!(
+            compiled.op(0),
+            Some(CompiledOp {
+                instruction: Instruction::Add,
+                args: [Arg::Immediate(2), Arg::Immediate(3), Arg::Position(7)],
+                len: 4,
+            })
+        );
+        assert_eq!(compiled.op(1), None);
+        assert_eq!(compiled.owner(3), Some(0));
+        assert_eq!(compiled.owner(7), None);
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        let code = crate::template::read_file("examples", crate::day!(5));
+        let mut compiled = Intcode::new(&code);
+        compiled.compile();
+        assert_eq!(
+            compiled.clone().run_simplen(&[1]),
+            Intcode::new(&code).run_simplen(&[1])
+        );
+        assert_eq!(
+            compiled.run_simple(&[5]),
+            Intcode::new(&code).run_simple(&[5])
+        );
+    }
+
+    #[test]
+    fn test_self_modifying() {
+        // Overwrites the opcode at 4 (add -> mul) before running it
+        let mut intcode = Intcode::new("1101,1,1101,4,1101,3,4,11,4,11,99,0");
+        intcode.compile();
+        assert_eq!(intcode.run_simple::<i64>(&[]), Some(12));
+
+        // And after being compiled, from outside
+        let mut intcode = Intcode::new("1101,3,4,7,4,7,99,0");
+        intcode.compile();
+        intcode.set_code(2, 10);
+        assert_eq!(intcode.run_simple::<i64>(&[]), Some(13));
+
+        // Writing the missing parameters of an op running off the end
+        let mut intcode = Intcode::new("3,3,7");
+        intcode.compile();
+        assert_eq!(intcode.compiled().unwrap().ops(), 1);
+        assert!(intcode.try_run(|| Some(-2), |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_sparse() {
+        // Only the dense pages are compiled after a write to a huge address
+        let mut intcode = Intcode::new("109,1099511627776,21101,1,2,0,99");
+        assert_eq!(intcode.run_simple::<i64>(&[]), None);
+        assert_eq!(intcode.code_at(1 << 40), 3);
+        intcode.compile();
+        assert_eq!(intcode.compiled().unwrap().ops(), 3);
+        assert_eq!(intcode.disassemble().ops().count(), 3);
+    }
+}
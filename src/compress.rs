@@ -0,0 +1,192 @@
+use itertools::Itertools;
+use std::fmt;
+
+// A sequence of tokens written as a main routine of calls to functions (A, B,
+// C, ...), each a run of the tokens, as for day 17's vacuum robot
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Decomposition<T> {
+    pub main: Vec<usize>,
+    pub functions: Vec<Vec<T>>,
+}
+
+impl<T> Default for Decomposition<T> {
+    fn default() -> Self {
+        Self {
+            main: vec![],
+            functions: vec![],
+        }
+    }
+}
+
+fn function_name(i: usize) -> char {
+    (b'A' + i as u8) as char
+}
+
+// Length of the tokens written on a line separated by commas
+fn line_len<T: fmt::Display>(tokens: &[T]) -> usize {
+    tokens.iter().map(|t| t.to_string().len()).sum::<usize>() + tokens.len().saturating_sub(1)
+}
+
+impl<T: Clone> Decomposition<T> {
+    pub fn expand(&self) -> Vec<T> {
+        self.main
+            .iter()
+            .flat_map(|&f| self.functions[f].iter().cloned())
+            .collect()
+    }
+}
+
+impl<T: fmt::Display> Decomposition<T> {
+    // The main routine then each function, a line each (and a blank line for
+    // each function not needed, up to the number given)
+    pub fn program(&self, functions: usize) -> String {
+        let main = self.main.iter().map(|&f| function_name(f)).join(",");
+        let mut program = format!("{}\n", main);
+        for i in 0..functions {
+            let function = self.functions.get(i).map(|f| f.iter().join(","));
+            program.push_str(&function.unwrap_or_default());
+            program.push('\n');
+        }
+        program
+    }
+}
+
+// Searches for ways of writing tokens with at most the given number of
+// functions, with no line (main routine or function) longer than the limit.
+// Functions are named in the order they're first called, so each way is only
+// found once.
+#[derive(Clone, Copy, Debug)]
+pub struct Compressor {
+    pub functions: usize,
+    pub limit: usize,
+}
+
+impl Compressor {
+    pub fn new(functions: usize, limit: usize) -> Self {
+        assert!(functions <= 26, "Functions are named A to Z");
+        Self { functions, limit }
+    }
+
+    pub fn decompose<T>(&self, tokens: &[T]) -> Vec<Decomposition<T>>
+    where
+        T: Clone + Eq + fmt::Display,
+    {
+        let mut found = vec![];
+        self.search(tokens, &mut Decomposition::default(), &mut |d| {
+            found.push(d.clone());
+            false
+        });
+        found
+    }
+
+    // The first way found (trying longer functions first), or None if there
+    // isn't one
+    pub fn first<T>(&self, tokens: &[T]) -> Option<Decomposition<T>>
+    where
+        T: Clone + Eq + fmt::Display,
+    {
+        let mut found = None;
+        self.search(tokens, &mut Decomposition::default(), &mut |d| {
+            found = Some(d.clone());
+            true
+        });
+        found
+    }
+
+    // Depth first through the calls to make, with those to functions already
+    // defined tried first. Stops (returning true) when told to by the visitor.
+    fn search<T, F>(&self, tokens: &[T], current: &mut Decomposition<T>, visit: &mut F) -> bool
+    where
+        T: Clone + Eq + fmt::Display,
+        F: FnMut(&Decomposition<T>) -> bool,
+    {
+        if tokens.is_empty() {
+            return visit(current);
+        }
+        // Another call won't fit in the main routine
+        if 2 * current.main.len() + 1 > self.limit {
+            return false;
+        }
+
+        for f in 0..current.functions.len() {
+            if tokens.starts_with(&current.functions[f]) {
+                current.main.push(f);
+                let rest = &tokens[current.functions[f].len()..];
+                if self.search(rest, current, visit) {
+                    return true;
+                }
+                current.main.pop();
+            }
+        }
+
+        if current.functions.len() < self.functions {
+            let longest = (1..=tokens.len())
+                .take_while(|&len| line_len(&tokens[..len]) <= self.limit)
+                .last()
+                .unwrap_or(0);
+            for len in (1..=longest).rev() {
+                let function = &tokens[..len];
+                if current.functions.iter().any(|f| f == function) {
+                    continue;
+                }
+                current.main.push(current.functions.len());
+                current.functions.push(function.to_vec());
+                if self.search(&tokens[len..], current, visit) {
+                    return true;
+                }
+                current.functions.pop();
+                current.main.pop();
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(path: &str) -> Vec<&str> {
+        path.split(',').collect()
+    }
+
+    #[test]
+    fn test_decompose() {
+        let path = tokens("R,8,R,8,R,4,R,4,R,8,L,6,L,2,R,4,R,4,R,8,R,8,R,8,L,6,L,2");
+        let compressor = Compressor::new(3, 12);
+        let found = compressor.decompose(&path);
+        assert!(!found.is_empty());
+        for d in &found {
+            assert_eq!(d.expand(), path);
+            assert!(d.program(3).lines().all(|line| line.len() <= 12));
+        }
+        assert_eq!(found.iter().unique().count(), found.len());
+
+        let expected = Decomposition {
+            main: vec![0, 1, 2, 1, 0, 2],
+            functions: vec![tokens("R,8,R,8"), tokens("R,4,R,4,R,8"), tokens("L,6,L,2")],
+        };
+        assert!(found.contains(&expected));
+        assert_eq!(
+            expected.program(3),
+            "A,B,C,B,A,C\nR,8,R,8\nR,4,R,4,R,8\nL,6,L,2\n"
+        );
+        assert_eq!(compressor.first(&path).as_ref(), found.first());
+
+        // Functions needn't split the path between moves
+        let path = tokens("L,10,R,12,L,8,R,6,L,4,R,2");
+        let d = Compressor::new(2, 20).first(&path).unwrap();
+        assert_eq!(d.program(3), "A,B\nL,10,R,12,L,8,R,6,L\n4,R,2\n\n");
+    }
+
+    #[test]
+    fn test_none() {
+        // Nothing repeats, and it won't fit on one line
+        let path = tokens("L,10,R,12,L,8,R,6,L,4,R,2");
+        assert_eq!(Compressor::new(1, 20).first(&path), None);
+        assert!(Compressor::new(1, 20).decompose(&path).is_empty());
+        // Nor in two functions on shorter lines
+        assert_eq!(Compressor::new(2, 10).first(&path), None);
+        assert!(Compressor::new(2, 10).decompose(&path).is_empty());
+    }
+}
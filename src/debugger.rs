@@ -0,0 +1,361 @@
+use crate::{Instruction, Intcode, IntcodeError, Op, RunState, Snapshot, Undone};
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Stop {
+    Stepped,
+    Breakpoint(usize),
+    Watchpoint { addr: usize, old: i64, new: i64 },
+    NeedsInput,
+    Halted,
+    StepLimit,
+    OutOfFuel,
+    OutOfMemory(usize),
+    Exit(i64),
+    Error(IntcodeError),
+    // Stepped back to just before an output
+    Output(i64),
+    // Stepped back as far as the history goes
+    Start,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Stepped => write!(f, "Stepped"),
+            Stop::Breakpoint(addr) => write!(f, "Breakpoint at {}", addr),
+            Stop::Watchpoint { addr, old, new } => {
+                write!(f, "Watchpoint [{}] changed {} -> {}", addr, old, new)
+            }
+            Stop::NeedsInput => write!(f, "Waiting for input"),
+            Stop::Halted => write!(f, "Halted"),
+            Stop::StepLimit => write!(f, "Step limit reached"),
+            Stop::OutOfFuel => write!(f, "Out of fuel"),
+            Stop::Exit(code) => write!(f, "Exited with code {}", code),
+            Stop::OutOfMemory(addr) => write!(f, "Write to [{}] is beyond the memory limit", addr),
+            Stop::Error(err) => write!(f, "Error: {}", err),
+            Stop::Output(value) => write!(f, "Before output of {}", value),
+            Stop::Start => write!(f, "Reached the start of the history"),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Debugger {
+    pub intcode: Intcode,
+    pub input: VecDeque<i64>,
+    pub output: Vec<i64>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+}
+
+// Steps between history checkpoints, and how many to keep
+const HISTORY_INTERVAL: u64 = 10_000;
+const HISTORY_CHECKPOINTS: usize = 100;
+
+impl Debugger {
+    pub fn new(mut intcode: Intcode) -> Self {
+        intcode.record_history(HISTORY_INTERVAL, HISTORY_CHECKPOINTS);
+        Self {
+            intcode,
+            ..Default::default()
+        }
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> &BTreeSet<usize> {
+        &self.watchpoints
+    }
+
+    pub fn add_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn add_watchpoint(&mut self, addr: usize) -> bool {
+        self.watchpoints.insert(addr)
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: usize) -> bool {
+        self.watchpoints.remove(&addr)
+    }
+
+    // Number of instructions executed so far (since the history was last
+    // started, when a snapshot was restored)
+    pub fn steps(&self) -> u64 {
+        self.intcode.history().map_or(0, |history| history.steps())
+    }
+
+    pub fn push_input(&mut self, values: impl IntoIterator<Item = i64>) {
+        self.input.extend(values);
+    }
+
+    pub fn push_ascii(&mut self, text: &str) {
+        self.push_input(text.chars().map(|c| c as i64));
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::with_io(
+            &self.intcode,
+            &self.input.iter().copied().collect::<Vec<_>>(),
+            &self.output,
+        )
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.intcode = snapshot.intcode;
+        self.intcode
+            .record_history(HISTORY_INTERVAL, HISTORY_CHECKPOINTS);
+        self.input = snapshot.input.into();
+        self.output = snapshot.output;
+    }
+
+    pub fn current_op(&self) -> Option<Op> {
+        self.intcode.op_at(self.intcode.ptr())
+    }
+
+    // The len values from addr, or None if they run past the last address
+    pub fn memory(&self, addr: usize, len: usize) -> Option<Vec<i64>> {
+        let end = addr.checked_add(len)?;
+        Some((addr..end).map(|a| self.intcode.code_at(a)).collect())
+    }
+
+    pub fn step(&mut self) -> Stop {
+        let watched: Vec<_> = self
+            .watchpoints
+            .iter()
+            .map(|&addr| (addr, self.intcode.code_at(addr)))
+            .collect();
+
+        let input = &mut self.input;
+        let output = &mut self.output;
+        let result = self
+            .intcode
+            .try_run_for(|| input.pop_front(), |v| output.push(v), 1);
+
+        let stop = match result {
+            Ok(RunState::StepLimit | RunState::Output(_)) => Stop::Stepped,
+            Ok(RunState::NeedsInput) => return Stop::NeedsInput,
+            Ok(RunState::Halted) => return Stop::Halted,
+            Ok(RunState::OutOfFuel) => return Stop::OutOfFuel,
+            Ok(RunState::OutOfMemory(addr)) => return Stop::OutOfMemory(addr),
+            Ok(RunState::Exit(code)) => return Stop::Exit(code),
+            Err(err) => return Stop::Error(err),
+        };
+
+        for (addr, old) in watched {
+            let new = self.intcode.code_at(addr);
+            if new != old {
+                return Stop::Watchpoint { addr, old, new };
+            }
+        }
+        stop
+    }
+
+    // Runs until a breakpoint or watchpoint is hit, the machine stops, or
+    // max_steps instructions have executed
+    pub fn cont(&mut self, max_steps: usize) -> Stop {
+        for i in 0..max_steps {
+            let ptr = self.intcode.ptr();
+            if i > 0 && self.breakpoints.contains(&ptr) {
+                return Stop::Breakpoint(ptr);
+            }
+            match self.step() {
+                Stop::Stepped => {}
+                stop => return stop,
+            }
+        }
+        Stop::StepLimit
+    }
+
+    // Undoes the last instruction, giving back any input it consumed and
+    // taking back any output
+    pub fn step_back(&mut self) -> Option<Undone> {
+        let undone = self.intcode.step_back()?;
+        if let Some(value) = undone.input {
+            self.input.push_front(value);
+        }
+        if undone.instruction == Some(Instruction::Output) {
+            self.output.pop();
+        }
+        Some(undone)
+    }
+
+    // Steps back to just before the last write to addr
+    pub fn reverse_to_write(&mut self, addr: usize) -> Stop {
+        loop {
+            let new = self.intcode.code_at(addr);
+            match self.step_back() {
+                Some(Undone {
+                    write: Some((a, old)),
+                    ..
+                }) if a == addr => return Stop::Watchpoint { addr, old, new },
+                Some(_) => {}
+                None => return Stop::Start,
+            }
+        }
+    }
+
+    // Steps back to just before the last output
+    pub fn reverse_to_output(&mut self) -> Stop {
+        loop {
+            let value = self.output.last().copied();
+            match self.step_back() {
+                Some(undone) if undone.instruction == Some(Instruction::Output) => {
+                    return Stop::Output(value.unwrap_or_default())
+                }
+                Some(_) => {}
+                None => return Stop::Start,
+            }
+        }
+    }
+
+    // Decodes up to n instructions starting at addr (undecodable values are
+    // shown as data)
+    pub fn listing(&self, addr: usize, n: usize) -> Vec<String> {
+        let mut lines = vec![];
+        let mut addr = addr;
+        for _ in 0..n {
+            let marker = if addr == self.intcode.ptr() {
+                "=>"
+            } else if self.breakpoints.contains(&addr) {
+                " *"
+            } else {
+                "  "
+            };
+            match self.intcode.op_at(addr) {
+                Some(op) => {
+                    lines.push(format!("{} {:04}: {}", marker, addr, op));
+                    addr += op.len();
+                }
+                None => {
+                    lines.push(format!(
+                        "{} {:04}: db {}",
+                        marker,
+                        addr,
+                        self.intcode.code_at(addr)
+                    ));
+                    addr += 1;
+                }
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Effect, Extension, InstructionSet};
+
+    fn debugger() -> Debugger {
+        // Counts down from its input, outputting each value
+        Debugger::new(Intcode::new(
+            &crate::assemble(
+                "
+                    in -> [n]
+            loop:   out [n]
+                    add [n], #-1 -> [n]
+                    jt [n], #loop
+                    hlt
+            n:      db 0
+            ",
+            )
+            .unwrap(),
+        ))
+    }
+
+    #[test]
+    fn test_step() {
+        let mut debugger = debugger();
+        assert_eq!(debugger.step(), Stop::NeedsInput);
+        debugger.push_input([3]);
+        assert_eq!(debugger.step(), Stop::Stepped);
+        assert_eq!(debugger.current_op().unwrap().to_string(), "out [12]");
+        assert_eq!(debugger.step(), Stop::Stepped);
+        assert_eq!(debugger.output, [3]);
+        assert_eq!(debugger.steps(), 2);
+        assert_eq!(debugger.memory(11, 2), Some(vec![99, 3]));
+        assert_eq!(debugger.memory(usize::MAX, 2), None);
+    }
+
+    #[test]
+    fn test_step_count() {
+        // nop (an extension); hlt
+        let mut intcode = Intcode::new("50,99");
+        intcode.set_instruction_set(InstructionSet::full().with(Extension::new(
+            50,
+            "nop",
+            0,
+            false,
+            |_| Effect::Continue,
+        )));
+        let mut debugger = Debugger::new(intcode);
+        assert_eq!(debugger.step_back(), None);
+        assert_eq!(debugger.steps(), 0);
+        assert_eq!(debugger.step(), Stop::Stepped);
+        assert_eq!(debugger.steps(), 1);
+        assert_eq!(debugger.step(), Stop::Halted);
+        assert_eq!(debugger.steps(), 1);
+        assert!(debugger.step_back().is_some());
+        assert_eq!(debugger.steps(), 0);
+        assert_eq!(debugger.intcode.ptr(), 0);
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut debugger = debugger();
+        debugger.push_input([3]);
+        debugger.add_breakpoint(2);
+        assert_eq!(debugger.cont(1000), Stop::Breakpoint(2));
+        assert!(debugger.output.is_empty());
+        assert_eq!(debugger.cont(1000), Stop::Breakpoint(2));
+        assert_eq!(debugger.output, [3]);
+        debugger.remove_breakpoint(2);
+        assert_eq!(debugger.cont(1000), Stop::Halted);
+        assert_eq!(debugger.output, [3, 2, 1]);
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let mut debugger = debugger();
+        debugger.push_input([3]);
+        debugger.add_watchpoint(12);
+        assert_eq!(
+            debugger.cont(1000),
+            Stop::Watchpoint {
+                addr: 12,
+                old: 0,
+                new: 3
+            }
+        );
+        assert_eq!(
+            debugger.cont(1000),
+            Stop::Watchpoint {
+                addr: 12,
+                old: 3,
+                new: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_listing() {
+        let debugger = debugger();
+        assert_eq!(
+            debugger.listing(0, 3),
+            [
+                "=> 0000: in -> [12]",
+                "   0002: out [12]",
+                "   0004: add [12], #-1 -> [12]"
+            ]
+        );
+    }
+}
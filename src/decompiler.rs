@@ -0,0 +1,815 @@
+use crate::{Block, Cfg, Exit, Function, Instruction, Mode, Op, Param};
+use hashbrown::{HashMap, HashSet};
+use std::fmt::{self, Write};
+
+// Lifts a program into C-like pseudo-code, one function at a time. Stack
+// frames are recovered from the calling convention of the CFG: a function
+// starting with `arb #n` sees its return address at rel[-n] followed by its
+// arguments and then its locals, while rel[+1].. are the arguments of the
+// calls it makes.
+pub fn decompile(code: &[i64]) -> String {
+    let cfg = Cfg::new(code);
+    let mut arity: HashMap<usize, usize> = HashMap::new();
+    for block in cfg.blocks.values() {
+        if let Exit::Call {
+            target: Some(target),
+            ..
+        } = block.exit
+        {
+            let args = call_args(&block.ops).len();
+            let entry = arity.entry(target).or_default();
+            *entry = (*entry).max(args);
+        }
+    }
+
+    let mut returns: HashSet<usize> = cfg
+        .functions
+        .values()
+        .filter(|f| returns_value(&cfg, f))
+        .map(|f| f.entry)
+        .collect();
+    returns.extend(cfg.blocks.values().filter_map(|block| match block.exit {
+        Exit::Call {
+            target: Some(target),
+            ret,
+        } if result_used(&cfg, ret) => Some(target),
+        _ => None,
+    }));
+
+    let mut out = String::new();
+    for (i, function) in cfg.functions.values().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let decompiler = Decompiler {
+            cfg: &cfg,
+            function,
+            arity: match arity.get(&function.entry) {
+                Some(&n) => n,
+                None => inferred_arity(&cfg, function),
+            },
+            arities: &arity,
+            returns: &returns,
+            blocks: function.blocks.iter().map(|b| &cfg.blocks[b]).collect(),
+        };
+        out.push_str(&decompiler.function());
+    }
+    out
+}
+
+struct Decompiler<'a> {
+    cfg: &'a Cfg,
+    function: &'a Function,
+    arity: usize,
+    // Arguments each function is called with (at most)
+    arities: &'a HashMap<usize, usize>,
+    // Functions returning a value (in their first argument's slot)
+    returns: &'a HashSet<usize>,
+    blocks: Vec<&'a Block>,
+}
+
+// Where jumps should be written as break or continue
+#[derive(Clone, Copy, Default)]
+struct Scope {
+    head: Option<usize>,
+    exit: Option<usize>,
+    // Block whose jump just skips an else branch
+    join: Option<usize>,
+}
+
+struct Stmt {
+    dest: Option<Param>,
+    value: String,
+    text: String,
+    // The comparison made, for a less than or equals
+    compare: Option<Cond>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Cond {
+    Compare { op: Cmp, lhs: String, rhs: String },
+    // A value tested for being non-zero (or zero when negated)
+    Test { value: String, negated: bool },
+}
+
+impl Cond {
+    fn negate(&self) -> Self {
+        match self {
+            Cond::Compare { op, lhs, rhs } => Cond::Compare {
+                op: match op {
+                    Cmp::Eq => Cmp::Ne,
+                    Cmp::Ne => Cmp::Eq,
+                    Cmp::Lt => Cmp::Ge,
+                    Cmp::Ge => Cmp::Lt,
+                },
+                lhs: lhs.clone(),
+                rhs: rhs.clone(),
+            },
+            Cond::Test { value, negated } => Cond::Test {
+                value: value.clone(),
+                negated: !negated,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cond::Compare { op, lhs, rhs } => {
+                let op = match op {
+                    Cmp::Eq => "==",
+                    Cmp::Ne => "!=",
+                    Cmp::Lt => "<",
+                    Cmp::Ge => ">=",
+                };
+                write!(f, "{} {} {}", lhs, op, rhs)
+            }
+            Cond::Test {
+                value,
+                negated: false,
+            } => write!(f, "{}", value),
+            Cond::Test { value, .. } if value.contains(' ') => write!(f, "!({})", value),
+            Cond::Test { value, .. } => write!(f, "!{}", value),
+        }
+    }
+}
+
+// A line of structured pseudo-code, only rendered once the whole function
+// has been structured
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Code {
+    Stmt(String),
+    Label(usize),
+    Break,
+    // Of the loop with this head
+    Continue(usize),
+    Goto(usize),
+    // A conditional jump (break, continue or goto)
+    IfJump(Cond, Box<Code>),
+    If(Cond),
+    Else,
+    // Loops forever without a condition
+    While(Option<Cond>),
+    Do,
+    DoWhile(Cond),
+    End,
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Code::Stmt(text) => write!(f, "{}", text),
+            Code::Label(addr) => write!(f, "L{:04}:", addr),
+            Code::Break => write!(f, "break;"),
+            Code::Continue(_) => write!(f, "continue;"),
+            Code::Goto(addr) => write!(f, "goto L{:04};", addr),
+            Code::IfJump(cond, jump) => write!(f, "if ({}) {}", cond, jump),
+            Code::If(cond) => write!(f, "if ({}) {{", cond),
+            Code::Else => write!(f, "}} else {{"),
+            Code::While(Some(cond)) => write!(f, "while ({}) {{", cond),
+            Code::While(None) => write!(f, "while (true) {{"),
+            Code::Do => write!(f, "do {{"),
+            Code::DoWhile(cond) => write!(f, "}} while ({});", cond),
+            Code::End => write!(f, "}}"),
+        }
+    }
+}
+
+struct Line {
+    depth: usize,
+    code: Code,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Labels are outdented
+        let depth = match self.code {
+            Code::Label(_) => self.depth - 1,
+            _ => self.depth,
+        };
+        write!(f, "{}{}", "    ".repeat(depth), self.code)
+    }
+}
+
+impl Decompiler<'_> {
+    fn function(&self) -> String {
+        // Jump targets that couldn't be structured need labels, which are only
+        // known after a first pass
+        let mut gotos = HashSet::new();
+        let all = self.blocks.len();
+        self.range(0, all, 1, Scope::default(), &HashSet::new(), &mut gotos);
+        let lines = self.range(0, all, 1, Scope::default(), &gotos.clone(), &mut gotos);
+
+        let args: Vec<_> = (1..=self.arity).map(|i| format!("arg{}", i)).collect();
+        let mut out = format!(
+            "fn {}({}) {{\n",
+            fn_name(self.function.entry),
+            args.join(", ")
+        );
+        for line in lines {
+            let _ = writeln!(out, "{}", line);
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn index(&self, addr: usize) -> Option<usize> {
+        self.blocks.iter().position(|b| b.start == addr)
+    }
+
+    // Structures blocks[from..to] into lines of pseudo-code
+    fn range(
+        &self,
+        from: usize,
+        to: usize,
+        depth: usize,
+        scope: Scope,
+        labels: &HashSet<usize>,
+        gotos: &mut HashSet<usize>,
+    ) -> Vec<Line> {
+        let line = |code| Line { depth, code };
+        let mut lines = vec![];
+        let mut k = from;
+        while k < to {
+            let block = self.blocks[k];
+            if labels.contains(&block.start) {
+                lines.push(line(Code::Label(block.start)));
+            }
+
+            // A later jump back to this block makes it the head of a loop
+            let back = (k + 1..to).rev().find(|&m| match self.blocks[m].exit {
+                Exit::Jump(t) => t == block.start,
+                Exit::Branch { taken, .. } => taken == Some(block.start),
+                _ => false,
+            });
+            if let Some(m) = back.filter(|_| scope.head != Some(block.start)) {
+                let head = block.start;
+                let inner = Scope {
+                    head: Some(head),
+                    exit: Some(self.blocks[m].end()),
+                    join: None,
+                };
+                let mut body = self.range(k, m + 1, depth + 1, inner, labels, gotos);
+                if body.last().map(|l| &l.code) == Some(&Code::Continue(head)) {
+                    body.pop();
+                }
+                // Looping back conditionally is a do/while (or when there are
+                // other continues, which would skip the condition, a break)
+                if let Some(Code::IfJump(cond, jump)) = body.last().map(|l| &l.code) {
+                    if **jump == Code::Continue(head) {
+                        let cond = cond.clone();
+                        body.pop();
+                        let continues = body.iter().any(|l| match &l.code {
+                            Code::IfJump(_, jump) => **jump == Code::Continue(head),
+                            code => *code == Code::Continue(head),
+                        });
+                        if continues {
+                            body.push(Line {
+                                depth: depth + 1,
+                                code: Code::IfJump(cond.negate(), Box::new(Code::Break)),
+                            });
+                        } else {
+                            lines.push(line(Code::Do));
+                            lines.extend(body);
+                            lines.push(line(Code::DoWhile(cond)));
+                            k = m + 1;
+                            continue;
+                        }
+                    }
+                }
+                match body.first().map(|l| &l.code) {
+                    Some(Code::IfJump(cond, jump)) if **jump == Code::Break => {
+                        lines.push(line(Code::While(Some(cond.negate()))));
+                        body.remove(0);
+                    }
+                    _ => lines.push(line(Code::While(None))),
+                }
+                lines.extend(body);
+                lines.push(line(Code::End));
+                k = m + 1;
+                continue;
+            }
+
+            let (mut stmts, patches) = self.statements(block);
+            match block.exit {
+                Exit::Next(_) => {
+                    lines.extend(stmts.into_iter().map(|s| line(Code::Stmt(s.text))));
+                    k += 1;
+                }
+                Exit::Branch { taken, next } => {
+                    let cond = self.condition(block, &mut stmts, &patches);
+                    lines.extend(stmts.into_iter().map(|s| line(Code::Stmt(s.text))));
+                    let end = self.blocks.get(to).map_or(usize::MAX, |b| b.start);
+                    let jump = |code| line(Code::IfJump(cond.clone(), Box::new(code)));
+                    match taken {
+                        Some(t) if Some(t) == scope.exit => {
+                            lines.push(jump(Code::Break));
+                            k += 1;
+                        }
+                        Some(t) if Some(t) == scope.head => {
+                            lines.push(jump(Code::Continue(t)));
+                            k += 1;
+                        }
+                        Some(t) if t > next && (t <= end || Some(t) == scope.exit) => {
+                            let tk = self.index(t).filter(|&i| i <= to).unwrap_or(to);
+                            // A then branch ending in a jump past the else
+                            // branch makes this an if/else
+                            let skip = tk
+                                .checked_sub(1)
+                                .filter(|&i| i > k)
+                                .and_then(|i| match self.blocks[i].exit {
+                                    Exit::Jump(e) if e > t => self.index(e),
+                                    _ => None,
+                                })
+                                .filter(|&e| e <= to);
+                            lines.push(line(Code::If(cond.negate())));
+                            match skip {
+                                Some(e) => {
+                                    let join = Scope {
+                                        join: Some(tk - 1),
+                                        ..scope
+                                    };
+                                    lines.extend(self.range(
+                                        k + 1,
+                                        tk,
+                                        depth + 1,
+                                        join,
+                                        labels,
+                                        gotos,
+                                    ));
+                                    lines.push(line(Code::Else));
+                                    lines.extend(self.range(
+                                        tk,
+                                        e,
+                                        depth + 1,
+                                        scope,
+                                        labels,
+                                        gotos,
+                                    ));
+                                    k = e;
+                                }
+                                None => {
+                                    lines.extend(self.range(
+                                        k + 1,
+                                        tk,
+                                        depth + 1,
+                                        scope,
+                                        labels,
+                                        gotos,
+                                    ));
+                                    k = tk;
+                                }
+                            }
+                            lines.push(line(Code::End));
+                        }
+                        Some(t) => {
+                            gotos.insert(t);
+                            lines.push(jump(Code::Goto(t)));
+                            k += 1;
+                        }
+                        None => {
+                            let target = self.arg(block.ops.last().unwrap(), 1, &patches);
+                            lines.push(jump(Code::Stmt(format!("goto *{};", target))));
+                            k += 1;
+                        }
+                    }
+                }
+                exit => {
+                    let result = match stmts.last() {
+                        Some(stmt) if exit == Exit::Return && stmt.dest == Some(self.result()) => {
+                            stmts.pop().map(|stmt| stmt.value)
+                        }
+                        _ => None,
+                    };
+                    lines.extend(stmts.into_iter().map(|s| line(Code::Stmt(s.text))));
+                    let last = block.ops.last().unwrap();
+                    let code = match exit {
+                        Exit::Jump(_) if scope.join == Some(k) => {
+                            k += 1;
+                            continue;
+                        }
+                        Exit::Jump(t) if Some(t) == scope.head => Code::Continue(t),
+                        Exit::Jump(t) if Some(t) == scope.exit => Code::Break,
+                        Exit::Jump(t) => {
+                            gotos.insert(t);
+                            Code::Goto(t)
+                        }
+                        Exit::Call { target, .. } => {
+                            let mut args: Vec<_> = call_args(&block.ops)
+                                .iter()
+                                .map(|op| self.value(op, &patches))
+                                .collect();
+                            // Arguments set up before an earlier branch are
+                            // left in their slots
+                            let arity = target.and_then(|t| self.arities.get(&t));
+                            for offset in args.len()..arity.copied().unwrap_or(0) {
+                                args.push(self.slot(offset as i64 + 1));
+                            }
+                            let callee = match target {
+                                Some(t) if self.cfg.functions.contains_key(&t) => fn_name(t),
+                                Some(t) => format!("L{:04}", t),
+                                None => format!("(*{})", self.arg(last, 1, &patches)),
+                            };
+                            let call = format!("{}({});", callee, args.join(", "));
+                            Code::Stmt(match target {
+                                Some(t) if self.returns.contains(&t) => {
+                                    format!("{} = {}", self.slot(1), call)
+                                }
+                                _ => call,
+                            })
+                        }
+                        Exit::Return => Code::Stmt(match result {
+                            Some(value) => format!("return {};", value),
+                            None if self.returns.contains(&self.function.entry) => {
+                                format!("return {};", self.slot(self.result().value))
+                            }
+                            None => "return;".to_string(),
+                        }),
+                        Exit::Indirect => {
+                            Code::Stmt(format!("goto *{};", self.arg(last, 1, &patches)))
+                        }
+                        _ => Code::Stmt("halt();".to_string()),
+                    };
+                    lines.push(line(code));
+                    k += 1;
+                }
+            }
+        }
+        lines
+    }
+
+    // Statements for the body of a block (without its terminator, the
+    // pushes making up a call or the frame setup and teardown)
+    fn statements(&self, block: &Block) -> (Vec<Stmt>, HashMap<usize, String>) {
+        let mut ops = &block.ops[..];
+        match block.exit {
+            Exit::Next(_) => {}
+            Exit::Call { .. } => ops = &ops[..ops.len() - 2 - call_args(ops).len()],
+            _ => ops = &ops[..ops.len() - 1],
+        }
+        if block.start == self.function.entry && self.function.frame > 0 {
+            ops = ops.get(1..).unwrap_or_default();
+        }
+        if block.exit == Exit::Return {
+            if let Some(last) = ops.last() {
+                if last.instruction == Instruction::ShiftBase
+                    && last.params[0]
+                        == (Param {
+                            mode: Mode::Immediate,
+                            value: -self.function.frame,
+                        })
+                {
+                    ops = &ops[..ops.len() - 1];
+                }
+            }
+        }
+
+        // Values written into the parameters of later instructions in the
+        // block (i.e. pointer dereferences) are folded into those instructions
+        let mut patches: HashMap<usize, String> = HashMap::new();
+        let mut stmts = vec![];
+        for op in ops {
+            // Jumps that are never taken don't do anything
+            if op.jump_always() == Some(false) {
+                continue;
+            }
+            let dest = op.instruction.writes().then(|| *op.params.last().unwrap());
+            if let Some(dest) = dest.filter(|d| d.mode == Mode::Position) {
+                let addr = dest.value as usize;
+                let patched = block
+                    .ops
+                    .iter()
+                    .any(|o| o.addr > op.addr && addr > o.addr && addr < o.addr + o.len());
+                if patched {
+                    patches.insert(addr, self.value(op, &patches));
+                    continue;
+                }
+            }
+            let value = self.value(op, &patches);
+            let text = match op.instruction {
+                Instruction::Output => format!("output({});", value),
+                Instruction::ShiftBase => format!("rb += {};", value),
+                _ => format!("{} = {};", self.operand(dest.as_ref().unwrap()), value),
+            };
+            let compare = match op.instruction {
+                Instruction::Equals => Some(Cmp::Eq),
+                Instruction::LessThan => Some(Cmp::Lt),
+                _ => None,
+            }
+            .map(|cmp| Cond::Compare {
+                op: cmp,
+                lhs: self.arg(op, 0, &patches),
+                rhs: self.arg(op, 1, &patches),
+            });
+            stmts.push(Stmt {
+                dest,
+                value,
+                text,
+                compare,
+            });
+        }
+        (stmts, patches)
+    }
+
+    // Where a function leaves its return value
+    fn result(&self) -> Param {
+        Param {
+            mode: Mode::Relative,
+            value: 1 - self.function.frame,
+        }
+    }
+
+    // Condition under which the block's branch is taken, folding in the
+    // comparison computing it when that was the last statement
+    fn condition(
+        &self,
+        block: &Block,
+        stmts: &mut Vec<Stmt>,
+        patches: &HashMap<usize, String>,
+    ) -> Cond {
+        let jump = block.ops.last().unwrap();
+        let tested = jump.params[0];
+        let cond = match stmts.last() {
+            Some(stmt) if stmt.dest == Some(tested) && stmt.compare.is_some() => {
+                stmts.pop().unwrap().compare.unwrap()
+            }
+            _ => Cond::Test {
+                value: self.arg(jump, 0, patches),
+                negated: false,
+            },
+        };
+        if jump.instruction == Instruction::JumpIfTrue {
+            cond
+        } else {
+            cond.negate()
+        }
+    }
+
+    // Operand i of op, taking into account any value written into it
+    fn arg(&self, op: &Op, i: usize, patches: &HashMap<usize, String>) -> String {
+        let param = &op.params[i];
+        match patches.get(&(op.addr + 1 + i)) {
+            Some(expr) => match param.mode {
+                Mode::Immediate => expr.clone(),
+                Mode::Position => format!("mem[{}]", expr),
+                Mode::Relative => format!("rb[{}]", expr),
+            },
+            // Reading a patched parameter as data
+            None => match patches.get(&(param.value as usize)) {
+                Some(expr) if param.mode == Mode::Position && expr.contains(' ') => {
+                    format!("({})", expr)
+                }
+                Some(expr) if param.mode == Mode::Position => expr.clone(),
+                _ => self.operand(param),
+            },
+        }
+    }
+
+    fn value(&self, op: &Op, patches: &HashMap<usize, String>) -> String {
+        let arg = |i: usize| self.arg(op, i, patches);
+        let imm = |i: usize| (op.params[i].mode == Mode::Immediate).then_some(op.params[i].value);
+        match op.instruction {
+            Instruction::Add => match (imm(0), imm(1)) {
+                (Some(0), _) => arg(1),
+                (_, Some(0)) => arg(0),
+                (_, Some(b)) if b < 0 => format!("{} - {}", arg(0), -b),
+                _ => format!("{} + {}", arg(0), arg(1)),
+            },
+            Instruction::Multiply => match (imm(0), imm(1)) {
+                (Some(1), _) => arg(1),
+                (_, Some(1)) => arg(0),
+                (_, Some(-1)) => format!("-{}", arg(0)),
+                _ => format!("{} * {}", arg(0), arg(1)),
+            },
+            Instruction::LessThan => format!("{} < {}", arg(0), arg(1)),
+            Instruction::Equals => format!("{} == {}", arg(0), arg(1)),
+            Instruction::Input => "input()".to_string(),
+            Instruction::Output | Instruction::ShiftBase => arg(0),
+            _ => String::new(),
+        }
+    }
+
+    fn operand(&self, param: &Param) -> String {
+        match param.mode {
+            Mode::Immediate => param.value.to_string(),
+            Mode::Position => format!("g{}", param.value),
+            Mode::Relative => self.slot(param.value),
+        }
+    }
+
+    // Name of a stack slot relative to the function's frame
+    fn slot(&self, offset: i64) -> String {
+        let frame = self.function.frame;
+        if offset > 0 {
+            return format!("tmp{}", offset);
+        }
+        if frame == 0 || offset < -frame {
+            return format!("rb[{}]", offset);
+        }
+        let k = (offset + frame) as usize;
+        if k == 0 {
+            "ret".to_string()
+        } else if k <= self.arity {
+            format!("arg{}", k)
+        } else {
+            format!("local{}", k - self.arity)
+        }
+    }
+}
+
+// A function returns a value if it writes its first argument's slot just
+// before returning
+fn returns_value(cfg: &Cfg, function: &Function) -> bool {
+    let result = Param {
+        mode: Mode::Relative,
+        value: 1 - function.frame,
+    };
+    function.frame > 0
+        && function.blocks.iter().any(|start| {
+            let block = &cfg.blocks[start];
+            let ops = &block.ops;
+            block.exit == Exit::Return
+                && ops.len() >= 3
+                && ops[ops.len() - 3].instruction.writes()
+                && ops[ops.len() - 3].params.last() == Some(&result)
+        })
+}
+
+// Whether the code a call returns to reads the result (left in rel[+1])
+// before overwriting it
+fn result_used(cfg: &Cfg, ret: usize) -> bool {
+    let result = Param {
+        mode: Mode::Relative,
+        value: 1,
+    };
+    let Some(block) = cfg.blocks.get(&ret) else {
+        return false;
+    };
+    for op in &block.ops {
+        let (reads, writes) = if op.instruction.writes() {
+            op.params.split_at(op.params.len() - 1)
+        } else {
+            (&op.params[..], &[][..])
+        };
+        if reads.contains(&result) {
+            return true;
+        }
+        if writes.contains(&result) || op.instruction == Instruction::ShiftBase {
+            return false;
+        }
+    }
+    false
+}
+
+// Number of arguments a function (only ever called through a pointer) seems
+// to take: the leading frame slots which are read before being written
+fn inferred_arity(cfg: &Cfg, function: &Function) -> usize {
+    let mut first_read: HashMap<i64, bool> = HashMap::new();
+    for start in &function.blocks {
+        for op in &cfg.blocks[start].ops {
+            let dest = op.instruction.writes().then(|| op.params.len() - 1);
+            for (i, param) in op.params.iter().enumerate() {
+                if param.mode == Mode::Relative && param.value < 0 {
+                    first_read
+                        .entry(param.value + function.frame)
+                        .or_insert(Some(i) != dest);
+                }
+            }
+        }
+    }
+    (1..function.frame)
+        .take_while(|k| first_read.get(k) == Some(&true))
+        .count()
+}
+
+fn fn_name(entry: usize) -> String {
+    format!("fn_{:04}", entry)
+}
+
+// Ops computing the arguments of the call ending a block: writes to rel[+1]..
+// directly before the return address is pushed
+fn call_args(ops: &[Op]) -> Vec<&Op> {
+    let mut args: Vec<&Op> = vec![];
+    for op in ops.iter().rev().skip(2) {
+        let slot = op.params.last().filter(|_| op.instruction.writes());
+        match slot {
+            Some(p)
+                if p.mode == Mode::Relative
+                    && p.value >= 1
+                    && args.iter().all(|a| a.params.last() != Some(p)) =>
+            {
+                args.push(op)
+            }
+            _ => break,
+        }
+    }
+    args.sort_by_key(|op| op.params.last().unwrap().value);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_code;
+
+    #[test]
+    fn test_decompile() {
+        // Outputs double its input (unless it's zero), using a function
+        let code = assemble_code(
+            "
+                    arb #100
+                    in -> rel[+1]
+                    add #ret, #0 -> rel[+0]
+                    jt #1, #double
+            ret:    out rel[+1]
+                    hlt
+            double: arb #2
+                    jf rel[-1], #done
+                    mul rel[-1], #2 -> rel[-1]
+            done:   arb #-2
+                    jt #1, rel[+0]
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            decompile(&code),
+            "\
+fn fn_0000() {
+    tmp1 = fn_0014(input());
+    output(tmp1);
+    halt();
+}
+
+fn fn_0014(arg1) {
+    if (arg1) {
+        arg1 = arg1 * 2;
+    }
+    return arg1;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_jump_never_taken() {
+        assert_eq!(
+            decompile(&[1106, 1, 0, 99]),
+            "fn fn_0000() {\n    halt();\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_negate_pointer() {
+        // The operand read through a pointer contains a comparison, which
+        // mustn't be what's negated
+        let code = assemble_code(
+            "
+                    eq [20], [21] -> [test+1]
+            test:   jt [0], #done
+                    out #1
+            done:   hlt
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            decompile(&code),
+            "\
+fn fn_0000() {
+    if (!(mem[g20 == g21])) {
+        output(1);
+    }
+    halt();
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_day25() {
+        let code = crate::template::read_file("examples", crate::day!(25));
+        let source = decompile(&crate::Intcode::new(&code).memory().to_vec());
+        // Calls through a function pointer argument in a loop
+        assert!(source.contains(
+            "\
+fn fn_1130(arg1, arg2, arg3, arg4) {
+    local1 = 0;
+    while (local1 != arg2) {
+        tmp1 = local1 * arg3;
+        (*arg4)(tmp1 + arg1, local1);
+        local1 = local1 + 1;
+    }
+    return;
+}"
+        ));
+        assert!(source.contains("fn_1174(arg1, 1256);"));
+    }
+}
@@ -0,0 +1,450 @@
+use crate::{Arithmetic, Instruction, Intcode, IntcodeError, Mode, RunState};
+use itertools::Itertools;
+use std::fmt;
+
+// Memory (beyond the program) used for data, and the limit on how far a run
+// may write. The limit keeps wild writes from allocating huge memories.
+const DATA: usize = 8;
+const MAX_MEMORY: usize = 1024;
+
+// Ways of running a program that should behave exactly like the reference
+// (a fresh machine run to completion with Intcode::try_run)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Variant {
+    // Without fuel, so run with nothing checked between instructions, and
+    // stopped by a step limit of the same size instead
+    Plain,
+    PlainCompiled,
+    Compiled,
+    // Only compared when the reference doesn't overflow
+    Wrapping,
+    Big,
+    // Compiled, cloned after FuzzCase::split steps and the clone finished
+    // after the original
+    Cloned,
+    // The machine Cloned was cloned from, as it is once both have finished
+    // (so writes leaking from the clone show)
+    Original,
+    // Halting after each output and resuming
+    Resumed,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 8] = [
+        Variant::Plain,
+        Variant::PlainCompiled,
+        Variant::Compiled,
+        Variant::Wrapping,
+        Variant::Big,
+        Variant::Cloned,
+        Variant::Original,
+        Variant::Resumed,
+    ];
+}
+
+// Everything observable about how a run ended
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Outcome {
+    pub state: Result<RunState, IntcodeError>,
+    pub output: Vec<i64>,
+    pub memory: Vec<i64>,
+    pub ptr: usize,
+    pub relbase: i64,
+}
+
+impl Outcome {
+    fn new(intcode: &Intcode, state: Result<RunState, IntcodeError>, output: Vec<i64>) -> Self {
+        Self {
+            state,
+            output,
+            memory: intcode.memory().to_vec(),
+            ptr: intcode.ptr(),
+            relbase: intcode.relbase(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FuzzCase {
+    pub code: Vec<i64>,
+    pub inputs: Vec<i64>,
+    // Instructions each run may execute
+    pub fuel: u64,
+    // Steps before cloning (for Variant::Cloned)
+    pub split: usize,
+}
+
+impl fmt::Display for FuzzCase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "code: {}", self.code.iter().join(","))?;
+        writeln!(f, "inputs: {}", self.inputs.iter().join(","))?;
+        write!(f, "fuel: {}, split: {}", self.fuel, self.split)
+    }
+}
+
+impl FuzzCase {
+    fn machine(&self) -> Intcode {
+        let mut intcode = Intcode::new(&self.code.iter().join(","));
+        intcode.set_fuel(Some(self.fuel));
+        intcode.set_max_memory(Some(MAX_MEMORY));
+        intcode
+    }
+
+    pub fn reference(&self) -> Outcome {
+        let mut intcode = self.machine();
+        let mut inputs = self.inputs.iter().copied();
+        let mut output = vec![];
+        let state = intcode.try_run(|| inputs.next(), |v| output.push(v));
+        Outcome::new(&intcode, state, output)
+    }
+
+    pub fn run(&self, variant: Variant) -> Outcome {
+        let mut intcode = self.machine();
+        let mut inputs = self.inputs.iter().copied();
+        let mut output = vec![];
+        let state = match variant {
+            Variant::Plain | Variant::PlainCompiled => {
+                intcode.set_fuel(None);
+                if variant == Variant::PlainCompiled {
+                    intcode.compile();
+                }
+                let steps = self.fuel as usize;
+                match intcode.try_run_for(|| inputs.next(), |v| output.push(v), steps) {
+                    Ok(RunState::StepLimit) => Ok(RunState::OutOfFuel),
+                    state => state,
+                }
+            }
+            Variant::Compiled => {
+                intcode.compile();
+                intcode.try_run(|| inputs.next(), |v| output.push(v))
+            }
+            Variant::Wrapping | Variant::Big => {
+                intcode.set_arithmetic(match variant {
+                    Variant::Wrapping => Arithmetic::Wrapping,
+                    _ => Arithmetic::Big,
+                });
+                intcode.try_run(|| inputs.next(), |v| output.push(v))
+            }
+            Variant::Cloned | Variant::Original => {
+                intcode.compile();
+                let state = intcode.try_run_for(|| inputs.next(), |v| output.push(v), self.split);
+                if !matches!(state, Ok(RunState::StepLimit)) {
+                    return Outcome::new(&intcode, state, output);
+                }
+                let mut clone = intcode.clone();
+                let (mut clone_inputs, mut clone_output) = (inputs.clone(), output.clone());
+                let state = intcode.try_run(|| inputs.next(), |v| output.push(v));
+                let clone_state = clone.try_run(|| clone_inputs.next(), |v| clone_output.push(v));
+                return match variant {
+                    Variant::Cloned => Outcome::new(&clone, clone_state, clone_output),
+                    _ => Outcome::new(&intcode, state, output),
+                };
+            }
+            Variant::Resumed => {
+                intcode.halt_after_output(true);
+                loop {
+                    let state = intcode.try_run(|| inputs.next(), |v| output.push(v));
+                    if !matches!(state, Ok(RunState::Output(_))) {
+                        break state;
+                    }
+                }
+            }
+        };
+        Outcome::new(&intcode, state, output)
+    }
+
+    // The first variant that behaves differently from the reference
+    pub fn check(&self) -> Option<Mismatch> {
+        let expected = self.reference();
+        let overflowed = matches!(expected.state, Err(IntcodeError::Overflow { .. }));
+        Variant::ALL
+            .into_iter()
+            .filter(|variant| !overflowed || !matches!(variant, Variant::Wrapping | Variant::Big))
+            .find_map(|variant| {
+                let actual = self.run(variant);
+                (actual != expected).then(|| Mismatch {
+                    variant,
+                    case: self.clone(),
+                    expected: expected.clone(),
+                    actual,
+                })
+            })
+    }
+
+    // Shrinks the case as far as it can while keeping the predicate true, by
+    // dropping and zeroing code and inputs, and reducing the fuel
+    pub fn minimise<F>(&self, failing: F) -> FuzzCase
+    where
+        F: Fn(&FuzzCase) -> bool,
+    {
+        let mut case = self.clone();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            let mut candidates = vec![];
+            for i in (0..case.code.len()).rev() {
+                let mut candidate = case.clone();
+                candidate.code.truncate(i);
+                candidates.push(candidate);
+            }
+            for i in 0..case.code.len() {
+                let mut candidate = case.clone();
+                candidate.code.remove(i);
+                candidates.push(candidate);
+            }
+            for i in 0..case.inputs.len() {
+                let mut candidate = case.clone();
+                candidate.inputs.remove(i);
+                candidates.push(candidate);
+            }
+            for i in 0..case.code.len() {
+                for value in [0, case.code[i] / 2] {
+                    if value != case.code[i] {
+                        let mut candidate = case.clone();
+                        candidate.code[i] = value;
+                        candidates.push(candidate);
+                    }
+                }
+            }
+            for i in 0..case.inputs.len() {
+                if case.inputs[i] != 0 {
+                    let mut candidate = case.clone();
+                    candidate.inputs[i] = 0;
+                    candidates.push(candidate);
+                }
+            }
+            for fuel in [case.fuel / 2, case.fuel.saturating_sub(1)] {
+                if fuel != case.fuel {
+                    candidates.push(FuzzCase {
+                        fuel,
+                        ..case.clone()
+                    });
+                }
+            }
+            if let Some(smaller) = candidates
+                .into_iter()
+                .find(|c| !c.code.is_empty() && failing(c))
+            {
+                case = smaller;
+                changed = true;
+            }
+        }
+        case
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mismatch {
+    pub variant: Variant,
+    pub case: FuzzCase,
+    pub expected: Outcome,
+    pub actual: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:?} differs from the reference", self.variant)?;
+        writeln!(f, "{}", self.case)?;
+        writeln!(f, "expected: {:?}", self.expected)?;
+        write!(f, "actual:   {:?}", self.actual)
+    }
+}
+
+impl Mismatch {
+    // The smallest case found on which the same variant still differs
+    pub fn minimise(&self) -> Mismatch {
+        let variant = self.variant;
+        let case = self.case.minimise(|case| {
+            case.check()
+                .is_some_and(|mismatch| mismatch.variant == variant)
+        });
+        case.check().unwrap()
+    }
+}
+
+// Xorshift, so runs are reproducible from a seed without a dependency
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    // In 0..n, or 0 when n is 0 (so a fuzzer's limits can be set to 0)
+    fn below(&mut self, n: usize) -> usize {
+        match n {
+            0 => 0,
+            _ => (self.next() % n as u64) as usize,
+        }
+    }
+
+    fn range(&mut self, low: i64, high: i64) -> i64 {
+        low + (self.next() % (high - low) as u64) as i64
+    }
+}
+
+// Generates random programs made of valid instructions (though they may
+// still jump into data, write over themselves or run off the end)
+pub struct Fuzzer {
+    rng: Rng,
+    pub max_ops: usize,
+    pub max_inputs: usize,
+    pub fuel: u64,
+}
+
+impl Fuzzer {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng(seed.max(1)),
+            max_ops: 24,
+            max_inputs: 6,
+            fuel: 500,
+        }
+    }
+
+    // Mostly small, sometimes large enough to overflow
+    fn value(&mut self) -> i64 {
+        match self.rng.below(16) {
+            0 => self.rng.range(1 << 30, 1 << 40) * [1, -1][self.rng.below(2)],
+            1 => [i64::MAX, i64::MIN, i64::MAX / 2][self.rng.below(3)],
+            _ => self.rng.range(-10, 50),
+        }
+    }
+
+    // Halts are rarer than the rest, so programs get a chance to do something
+    fn instruction(&mut self) -> Instruction {
+        loop {
+            let instruction = Instruction::ALL[self.rng.below(Instruction::ALL.len())];
+            if instruction != Instruction::Halt || self.rng.below(4) == 0 {
+                return instruction;
+            }
+        }
+    }
+
+    pub fn case(&mut self) -> FuzzCase {
+        let count = 1 + self.rng.below(self.max_ops);
+        let instructions: Vec<_> = (0..count).map(|_| self.instruction()).collect();
+        let starts: Vec<_> = instructions
+            .iter()
+            .scan(0, |addr, instruction| {
+                let start = *addr;
+                *addr += instruction.arity() + 1;
+                Some(start)
+            })
+            .collect();
+        let len = starts[count - 1] + instructions[count - 1].arity() + 1 + DATA;
+
+        let mut code = vec![];
+        for instruction in instructions {
+            let arity = instruction.arity();
+            let mut opcode = instruction.opcode();
+            let mut params = vec![];
+            for i in 0..arity {
+                let writes = instruction.writes() && i == arity - 1;
+                let target = !instruction.writes() && arity == 2 && i == 1;
+                let mode = match (writes, self.rng.below(3)) {
+                    (true, 0 | 1) | (false, 0) => Mode::Position,
+                    (false, 1) => Mode::Immediate,
+                    _ => Mode::Relative,
+                };
+                params.push(match mode {
+                    Mode::Position => self.rng.below(len) as i64,
+                    Mode::Immediate if target => starts[self.rng.below(count)] as i64,
+                    Mode::Immediate if instruction == Instruction::ShiftBase => {
+                        self.rng.range(-2, 6)
+                    }
+                    Mode::Immediate => self.value(),
+                    Mode::Relative => self.rng.range(0, 8),
+                });
+                opcode += mode.digit() * 10_i64.pow(i as u32 + 2);
+            }
+            code.push(opcode);
+            code.extend(params);
+        }
+        code.extend((0..DATA).map(|_| self.value()));
+
+        let inputs = (0..self.rng.below(self.max_inputs + 1))
+            .map(|_| self.value())
+            .collect();
+        FuzzCase {
+            code,
+            inputs,
+            fuel: self.fuel,
+            split: self.rng.below(self.fuel as usize),
+        }
+    }
+
+    // Checks this many random cases, returning the first mismatch found
+    // (minimised)
+    pub fn run(&mut self, cases: usize) -> Option<Mismatch> {
+        (0..cases).find_map(|_| self.case().check().map(|mismatch| mismatch.minimise()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzz() {
+        if let Some(mismatch) = Fuzzer::new(2019).run(2000) {
+            panic!("{}", mismatch);
+        }
+
+        // Unfuelled runs stop where the reference runs out of fuel
+        let case = FuzzCase {
+            code: vec![1105, 1, 0],
+            inputs: vec![],
+            fuel: 10,
+            split: 0,
+        };
+        assert_eq!(case.reference().state, Ok(RunState::OutOfFuel));
+        for variant in [Variant::Plain, Variant::PlainCompiled] {
+            assert_eq!(case.run(variant), case.reference());
+        }
+    }
+
+    #[test]
+    fn test_generates_valid_programs() {
+        let mut fuzzer = Fuzzer::new(1);
+        for _ in 0..100 {
+            let case = fuzzer.case();
+            assert!(crate::reachable_ops(&case.code).contains_key(&0));
+        }
+    }
+
+    #[test]
+    fn test_zero_limits() {
+        let mut fuzzer = Fuzzer::new(3);
+        fuzzer.fuel = 0;
+        fuzzer.max_ops = 0;
+        fuzzer.max_inputs = 0;
+        for _ in 0..10 {
+            let case = fuzzer.case();
+            assert_eq!((case.fuel, case.split), (0, 0));
+            assert!(case.inputs.is_empty());
+            assert_eq!(case.reference().state, Ok(RunState::OutOfFuel));
+            assert_eq!(case.check(), None);
+        }
+    }
+
+    #[test]
+    fn test_minimise() {
+        // Anything outputting a 3
+        let outputs_3 = |case: &FuzzCase| case.reference().output.contains(&3);
+        let mut fuzzer = Fuzzer::new(7);
+        let case = std::iter::repeat_with(|| fuzzer.case())
+            .find(|case| outputs_3(case) && case.code.len() > 10)
+            .unwrap();
+        let minimised = case.minimise(outputs_3);
+        assert!(outputs_3(&minimised));
+        assert!(minimised.code.len() < case.code.len());
+        assert!(minimised.inputs.is_empty());
+        assert_eq!(minimised.minimise(outputs_3), minimised);
+    }
+}
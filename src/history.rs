@@ -0,0 +1,343 @@
+use crate::{Instruction, Intcode, Memory};
+use num::BigInt;
+use std::collections::VecDeque;
+
+// An executed instruction, with what's needed to undo it
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Entry {
+    ptr: usize,
+    relbase: i64,
+    instruction: Option<Instruction>,
+    // Address written and the value it held before
+    write: Option<(usize, i64)>,
+    // The value written over, if it was too large for an i64
+    big: Option<BigInt>,
+}
+
+// An instruction that was stepped back over
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Undone {
+    pub ptr: usize,
+    pub relbase: i64,
+    // None for an extension instruction
+    pub instruction: Option<Instruction>,
+    // Address written and the value it has been restored to
+    pub write: Option<(usize, i64)>,
+    // Input consumed by the instruction, which should be given back
+    pub input: Option<i64>,
+}
+
+// Undo log of a machine's execution. Only the steps since the last
+// checkpoint are logged; checkpoints (cheap, copy-on-write, copies of the
+// machine) are taken every `interval` steps, keeping at most `checkpoints`
+// of them. Stepping back past the last checkpoint restores the one before
+// and replays forward from it using the inputs recorded since. Memory written
+// outside an instruction (e.g. from a debugger) can't be replayed, so a
+// checkpoint is also taken before the next instruction after such a write.
+#[derive(Clone)]
+pub struct History {
+    interval: u64,
+    max_checkpoints: usize,
+    steps: u64,
+    log: Vec<Entry>,
+    // Step to be executed next when each was taken
+    checkpoints: VecDeque<(u64, Intcode)>,
+    // Inputs consumed since the oldest checkpoint, by step
+    inputs: VecDeque<(u64, i64)>,
+    // Entry being executed, if any
+    current: Option<Entry>,
+    // Whether memory was written outside an instruction since the last
+    // checkpoint
+    edited: bool,
+}
+
+impl History {
+    pub fn new(interval: u64, checkpoints: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            max_checkpoints: checkpoints.max(1),
+            steps: 0,
+            log: vec![],
+            checkpoints: VecDeque::new(),
+            inputs: VecDeque::new(),
+            current: None,
+            edited: false,
+        }
+    }
+
+    // Number of instructions executed since recording started
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // The earliest step that can be stepped back to
+    pub fn earliest(&self) -> u64 {
+        self.checkpoints
+            .front()
+            .map_or(self.steps, |(step, _)| *step)
+    }
+
+    pub(crate) fn checkpoint_due(&self) -> bool {
+        self.edited
+            || self.steps % self.interval == 0
+                && self.checkpoints.back().map(|(step, _)| *step) != Some(self.steps)
+    }
+
+    pub(crate) fn checkpoint(&mut self, intcode: Intcode) {
+        self.log.clear();
+        self.edited = false;
+        // An edit replaces any checkpoint taken at the same step before it
+        if self.checkpoints.back().map(|(step, _)| *step) == Some(self.steps) {
+            self.checkpoints.pop_back();
+        }
+        self.checkpoints.push_back((self.steps, intcode));
+        if self.checkpoints.len() > self.max_checkpoints {
+            self.checkpoints.pop_front();
+            let earliest = self.earliest();
+            while self
+                .inputs
+                .front()
+                .is_some_and(|(step, _)| *step < earliest)
+            {
+                self.inputs.pop_front();
+            }
+        }
+    }
+
+    pub(crate) fn begin(&mut self, ptr: usize, relbase: i64, instruction: Option<Instruction>) {
+        self.current = Some(Entry {
+            ptr,
+            relbase,
+            instruction,
+            write: None,
+            big: None,
+        });
+    }
+
+    // Called for every write, recorded only when made by an instruction
+    pub(crate) fn write(&mut self, addr: usize, old: i64, big: Option<BigInt>) {
+        match &mut self.current {
+            Some(entry) => {
+                entry.write = Some((addr, old));
+                entry.big = big;
+            }
+            None => self.edited = true,
+        }
+    }
+
+    // Finishes the current instruction, which is forgotten if it didn't
+    // complete (e.g. was waiting for input)
+    pub(crate) fn end(&mut self, completed: bool, memory: &Memory) {
+        let Some(entry) = self.current.take() else {
+            return;
+        };
+        if !completed {
+            return;
+        }
+        if let (Some(Instruction::Input), Some((addr, _))) = (entry.instruction, entry.write) {
+            self.inputs.push_back((self.steps, memory.get(addr)));
+        }
+        self.log.push(entry);
+        self.steps += 1;
+    }
+
+    // Removes the last logged entry, with the input it consumed and the big
+    // value it wrote over (if any)
+    pub(crate) fn pop(&mut self) -> Option<(Undone, Option<BigInt>)> {
+        let entry = self.log.pop()?;
+        self.steps -= 1;
+        let input = match self.inputs.back() {
+            Some(&(step, value)) if step == self.steps => {
+                self.inputs.pop_back();
+                Some(value)
+            }
+            _ => None,
+        };
+        let undone = Undone {
+            ptr: entry.ptr,
+            relbase: entry.relbase,
+            instruction: entry.instruction,
+            write: entry.write,
+            input,
+        };
+        Some((undone, entry.big))
+    }
+
+    pub(crate) fn is_logged(&self) -> bool {
+        !self.log.is_empty()
+    }
+
+    // Drops the checkpoint matching the current state, returning the one
+    // before it along with the inputs needed to replay up to now
+    pub(crate) fn rewind(&mut self) -> Option<(u64, Intcode, Vec<i64>)> {
+        if self.checkpoints.back()?.0 == self.steps {
+            if self.checkpoints.len() == 1 {
+                return None;
+            }
+            self.checkpoints.pop_back();
+        }
+        let (step, intcode) = self.checkpoints.back()?.clone();
+        let inputs = self
+            .inputs
+            .iter()
+            .filter(|(s, _)| *s >= step)
+            .map(|(_, value)| *value)
+            .collect();
+        Some((step, intcode, inputs))
+    }
+
+    // Forgets everything from the given step on (which is about to be
+    // replayed)
+    pub(crate) fn truncate(&mut self, step: u64) {
+        self.steps = step;
+        self.log.clear();
+        self.edited = false;
+        while self.inputs.back().is_some_and(|(s, _)| *s >= step) {
+            self.inputs.pop_back();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Arithmetic, Debugger, Effect, Extension, InstructionSet, RunState, Stop};
+
+    // Counts up from its input, outputting each value, and halts at 10
+    const COUNTER: &str = "3,20,4,20,1001,20,1,20,1008,20,10,21,1006,21,2,99,0,0,0,0,0,0";
+
+    #[test]
+    fn test_step_back() {
+        let mut intcode = Intcode::new(COUNTER);
+        intcode.record_history(4, 2);
+        let mut before = vec![];
+        for _ in 0..20 {
+            before.push(intcode.clone());
+            intcode.run_for(|| Some(3), |_| {}, 1);
+        }
+        assert_eq!(intcode.history().unwrap().steps(), 20);
+        assert_eq!(intcode.history().unwrap().earliest(), 12);
+
+        // Back to the oldest checkpoint kept, through the log and then by
+        // replaying from the checkpoint before the last
+        for expected in before.iter().rev().take(8) {
+            let undone = intcode.step_back().unwrap();
+            assert_eq!(undone.ptr, expected.ptr());
+            assert_eq!(intcode.ptr(), expected.ptr());
+            assert_eq!(intcode.relbase(), expected.relbase());
+            assert_eq!(intcode.memory(), expected.memory());
+        }
+        assert_eq!(intcode.step_back(), None);
+    }
+
+    #[test]
+    fn test_step_back_after_set() {
+        let mut intcode = Intcode::new(COUNTER);
+        intcode.record_history(4, 10);
+        intcode.run_for(|| Some(3), |_| {}, 5);
+        let unedited = intcode.clone();
+        intcode.set_code(20, 6);
+
+        // Past the checkpoint at step 8, then back over it
+        let mut before = vec![];
+        for _ in 0..6 {
+            before.push(intcode.clone());
+            intcode.run_for(|| None, |_| {}, 1);
+        }
+        for expected in before.iter().rev() {
+            intcode.step_back().unwrap();
+            assert_eq!(intcode.ptr(), expected.ptr());
+            assert_eq!(intcode.memory(), expected.memory());
+        }
+        assert_eq!(intcode.code_at(20), 6);
+
+        // Stepping back over the set undoes it
+        intcode.step_back().unwrap();
+        intcode.run_for(|| None, |_| {}, 1);
+        assert_eq!(intcode.memory(), unedited.memory());
+    }
+
+    #[test]
+    fn test_replays_input() {
+        let mut intcode = Intcode::new(COUNTER);
+        intcode.record_history(2, 10);
+        intcode.run_for(|| Some(7), |_| {}, 5);
+        for _ in 0..4 {
+            intcode.step_back().unwrap();
+        }
+        let undone = intcode.step_back().unwrap();
+        assert_eq!(undone.instruction, Some(Instruction::Input));
+        assert_eq!(undone.write, Some((20, 0)));
+        assert_eq!(undone.input, Some(7));
+        assert_eq!(intcode.memory(), Intcode::new(COUNTER).memory());
+    }
+
+    #[test]
+    fn test_reverse_debugging() {
+        let mut debugger = Debugger::new(Intcode::new(COUNTER));
+        debugger.push_input([7]);
+        assert_eq!(debugger.cont(usize::MAX), Stop::Halted);
+        assert_eq!(debugger.output, [7, 8, 9]);
+
+        assert_eq!(debugger.reverse_to_output(), Stop::Output(9));
+        assert_eq!(debugger.output, [7, 8]);
+        assert_eq!(
+            debugger.reverse_to_write(20),
+            Stop::Watchpoint {
+                addr: 20,
+                old: 8,
+                new: 9
+            }
+        );
+        assert_eq!(debugger.intcode.ptr(), 4);
+        assert_eq!(debugger.reverse_to_output(), Stop::Output(8));
+        assert_eq!(debugger.output, [7]);
+        assert_eq!(debugger.reverse_to_write(30), Stop::Start);
+        assert_eq!(debugger.steps(), 0);
+        assert_eq!(debugger.input, [7]);
+        assert!(debugger.output.is_empty());
+    }
+
+    #[test]
+    fn test_step_back_extension() {
+        // sq #9 -> [8]; add [8], #1 -> [8]; hlt; db 5
+        let isa = InstructionSet::full().with(Extension::new(43, "sq", 2, true, |values| {
+            Effect::Write(values[0] * values[0])
+        }));
+        // Through the log, then by replaying from checkpoints
+        for interval in [100, 1] {
+            let mut intcode = Intcode::new("143,9,8,1001,8,1,8,99,5");
+            intcode.set_instruction_set(isa.clone());
+            intcode.record_history(interval, 10);
+            assert_eq!(intcode.run_with_no_io(), RunState::Halted);
+            assert_eq!(intcode.history().unwrap().steps(), 2);
+            assert_eq!(intcode.code_at(8), 82);
+
+            let undone = intcode.step_back().unwrap();
+            assert_eq!(undone.instruction, Some(Instruction::Add));
+            assert_eq!(intcode.code_at(8), 81);
+            let undone = intcode.step_back().unwrap();
+            assert_eq!(undone.instruction, None);
+            assert_eq!(undone.write, Some((8, 5)));
+            assert_eq!(intcode.ptr(), 0);
+            assert_eq!(intcode.code_at(8), 5);
+            assert_eq!(intcode.step_back(), None);
+        }
+    }
+
+    #[test]
+    fn test_step_back_big() {
+        // Squares [10] twice, from 2^40 to 2^80 then 2^160
+        let mut intcode = Intcode::new("2,10,10,10,2,10,10,10,99,0,1099511627776");
+        intcode.set_arithmetic(Arithmetic::Big);
+        intcode.record_history(100, 10);
+        assert_eq!(intcode.run_with_no_io(), RunState::Halted);
+        assert_eq!(intcode.big_at(10), BigInt::from(1) << 160);
+
+        intcode.step_back().unwrap();
+        assert_eq!(intcode.big_at(10), BigInt::from(1) << 80);
+        intcode.step_back().unwrap();
+        assert_eq!(intcode.big_at(10), BigInt::from(1) << 40);
+        assert_eq!(intcode.big_values().count(), 0);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::{reachable_ops, Instruction, Mode, Op};
+use hashbrown::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+// What an extension instruction does once run
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Effect {
+    Continue,
+    // Value for the destination parameter (for extensions that write)
+    Write(i64),
+    // Stops the machine with an exit code (staying on the instruction)
+    Exit(i64),
+    // Fails the run with an error
+    Fail(String),
+}
+
+type Handler = dyn Fn(&[i64]) -> Effect + Send + Sync;
+
+// An extra opcode. The handler is given the values of the parameters, other
+// than the last when the extension writes (which is where a written value
+// goes).
+#[derive(Clone)]
+pub struct Extension {
+    pub opcode: i64,
+    pub mnemonic: String,
+    pub params: usize,
+    pub writes: bool,
+    handler: Arc<Handler>,
+}
+
+impl fmt::Debug for Extension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.mnemonic, self.opcode)
+    }
+}
+
+impl Extension {
+    pub fn new<F>(opcode: i64, mnemonic: &str, params: usize, writes: bool, handler: F) -> Self
+    where
+        F: Fn(&[i64]) -> Effect + Send + Sync + 'static,
+    {
+        // Modes only fit in the digits of an i64 for so many parameters, and
+        // builtins take at most three
+        assert!(params <= 3, "Extensions take at most 3 parameters");
+        Self {
+            opcode,
+            mnemonic: mnemonic.to_string(),
+            params,
+            writes: writes && params > 0,
+            handler: Arc::new(handler),
+        }
+    }
+
+    // Prints its parameter to stderr
+    pub fn debug_print(opcode: i64) -> Self {
+        Self::new(opcode, "dbg", 1, false, |values| {
+            eprintln!("{}", values[0]);
+            Effect::Continue
+        })
+    }
+
+    // Fails unless its two parameters are equal
+    pub fn assert_eq(opcode: i64) -> Self {
+        Self::new(opcode, "assert", 2, false, |values| {
+            if values[0] == values[1] {
+                Effect::Continue
+            } else {
+                Effect::Fail(format!("Assertion failed: {} != {}", values[0], values[1]))
+            }
+        })
+    }
+
+    // Halts with its parameter as the exit code
+    pub fn exit(opcode: i64) -> Self {
+        Self::new(opcode, "exit", 1, false, |values| Effect::Exit(values[0]))
+    }
+
+    pub(crate) fn run(&self, values: &[i64]) -> Effect {
+        (self.handler)(values)
+    }
+}
+
+// How an instruction set treats an opcode (with its parameter modes)
+pub(crate) enum Lookup<'a> {
+    Builtin,
+    Extension(&'a Extension),
+    BadOpcode,
+    BadMode,
+}
+
+// The opcodes and parameter modes a machine accepts. Machines without one
+// accept the full instruction set; with one, anything outside it is a
+// BadOpcode or BadMode error. Presets match what each puzzle day introduced.
+#[derive(Clone, Debug)]
+pub struct InstructionSet {
+    instructions: Vec<Instruction>,
+    modes: Vec<Mode>,
+    extensions: HashMap<i64, Extension>,
+}
+
+impl Default for InstructionSet {
+    fn default() -> Self {
+        Self::full()
+    }
+}
+
+impl InstructionSet {
+    pub fn new(instructions: &[Instruction], modes: &[Mode]) -> Self {
+        Self {
+            instructions: instructions.to_vec(),
+            modes: modes.to_vec(),
+            extensions: HashMap::new(),
+        }
+    }
+
+    pub fn full() -> Self {
+        Self::new(
+            &Instruction::ALL,
+            &[Mode::Position, Mode::Immediate, Mode::Relative],
+        )
+    }
+
+    // Day 2: add, mul and hlt in position mode
+    pub fn day2() -> Self {
+        Self::new(
+            &[Instruction::Add, Instruction::Multiply, Instruction::Halt],
+            &[Mode::Position],
+        )
+    }
+
+    // Day 5: adds I/O, jumps and comparisons, and immediate mode
+    pub fn day5() -> Self {
+        let instructions: Vec<_> = Instruction::ALL
+            .into_iter()
+            .filter(|i| *i != Instruction::ShiftBase)
+            .collect();
+        Self::new(&instructions, &[Mode::Position, Mode::Immediate])
+    }
+
+    // Adds an extension, unless its opcode is already taken (including by
+    // a builtin instruction, even one not in this set)
+    pub fn register(&mut self, extension: Extension) -> bool {
+        let opcode = extension.opcode;
+        let builtin = Instruction::ALL.iter().any(|i| i.opcode() == opcode);
+        if builtin || !(1..100).contains(&opcode) || self.extensions.contains_key(&opcode) {
+            return false;
+        }
+        self.extensions.insert(opcode, extension);
+        true
+    }
+
+    pub fn with(mut self, extension: Extension) -> Self {
+        let opcode = extension.opcode;
+        assert!(self.register(extension), "Opcode {} is taken", opcode);
+        self
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    pub fn modes(&self) -> &[Mode] {
+        &self.modes
+    }
+
+    pub fn extensions(&self) -> impl Iterator<Item = &Extension> {
+        self.extensions.values()
+    }
+
+    pub fn supports(&self, op: &Op) -> bool {
+        self.instructions.contains(&op.instruction)
+            && op.params.iter().all(|p| self.modes.contains(&p.mode))
+    }
+
+    // Reachable instructions the set doesn't support
+    pub fn unsupported(&self, code: &[i64]) -> Vec<Op> {
+        reachable_ops(code)
+            .into_values()
+            .filter(|op| !self.supports(op))
+            .collect()
+    }
+
+    pub(crate) fn lookup(&self, value: i64) -> Lookup<'_> {
+        let (params, result) = match Instruction::decode(value) {
+            Some(instruction) if self.instructions.contains(&instruction) => {
+                (instruction.arity(), Lookup::Builtin)
+            }
+            Some(_) => return Lookup::BadOpcode,
+            None => match self.extensions.get(&(value % 100)) {
+                Some(extension) => (extension.params, Lookup::Extension(extension)),
+                None => return Lookup::BadOpcode,
+            },
+        };
+        let modes_ok = (0..params).all(|i| {
+            Mode::decode(value / 10_i64.pow(i as u32 + 2)).is_some_and(|m| self.modes.contains(&m))
+        });
+        if modes_ok {
+            result
+        } else {
+            Lookup::BadMode
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Intcode, IntcodeError, RunState};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_presets() {
+        let day2 = crate::template::read_file("examples", crate::day!(2));
+        let day5 = crate::template::read_file("examples", crate::day!(5));
+        assert!(InstructionSet::day2()
+            .unsupported(&Intcode::new(&day2).memory().to_vec())
+            .is_empty());
+        assert!(!InstructionSet::day2()
+            .unsupported(&Intcode::new(&day5).memory().to_vec())
+            .is_empty());
+
+        let mut intcode = Intcode::new(&day5);
+        intcode.set_instruction_set(InstructionSet::day5());
+        assert_eq!(
+            intcode.run_simple(&[5]),
+            Intcode::new(&day5).run_simple(&[5])
+        );
+
+        let mut intcode = Intcode::new(&day5);
+        intcode.compile();
+        intcode.set_instruction_set(InstructionSet::day2());
+        assert!(matches!(
+            intcode.try_run(|| Some(1), |_| {}),
+            Err(IntcodeError::BadOpcode { ptr: 0, .. } | IntcodeError::BadMode { ptr: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_extensions() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let log2 = log.clone();
+        let isa = InstructionSet::full()
+            .with(Extension::new(42, "log", 1, false, move |values| {
+                log2.lock().unwrap().push(values[0]);
+                Effect::Continue
+            }))
+            .with(Extension::new(43, "sq", 2, true, |values| {
+                Effect::Write(values[0] * values[0])
+            }))
+            .with(Extension::exit(44))
+            .with(Extension::assert_eq(45));
+
+        // log #7; sq #9 -> [12]; log [12]; exit #3; db 0
+        let mut intcode = Intcode::new("142,7,143,9,12,42,12,144,3,0,0,0,0");
+        intcode.set_instruction_set(isa.clone());
+        assert_eq!(intcode.run_with_no_io(), RunState::Exit(3));
+        assert_eq!(intcode.run_with_no_io(), RunState::Exit(3));
+        assert_eq!(*log.lock().unwrap(), [7, 81]);
+
+        let mut intcode = Intcode::new("1145,1,2,99");
+        intcode.set_instruction_set(isa.clone());
+        assert_eq!(
+            intcode.try_run(|| None, |_| {}),
+            Err(IntcodeError::Extension {
+                message: "Assertion failed: 1 != 2".to_string(),
+                ptr: 0,
+                relbase: 0
+            })
+        );
+
+        // Opcodes are only registered once, and never over builtins
+        let mut isa = isa;
+        assert!(!isa.register(Extension::exit(44)));
+        assert!(!isa.register(Extension::exit(9)));
+        assert!(isa.register(Extension::exit(50)));
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 3 parameters")]
+    fn test_too_many_params() {
+        Extension::new(42, "big", 17, false, |_| Effect::Continue);
+    }
+}
@@ -1,6 +1,13 @@
-use num::{cast, NumCast};
+use crate::compiler::{Arg, CompiledOp};
+use crate::{
+    Arithmetic, Compiled, Effect, Extension, History, InstructionSet, Lookup, Memory, Undone,
+};
+use hashbrown::{HashMap, HashSet};
+use num::{cast, BigInt, NumCast, ToPrimitive};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::sync::mpsc::{Receiver, Sender};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 pub trait IntcodeIO {
     fn input(&mut self) -> Option<i64>;
@@ -8,34 +15,42 @@ pub trait IntcodeIO {
     fn output(&mut self, value: i64);
 }
 
-#[derive(PartialEq)]
-enum Mode {
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
     Position,
     Immediate,
     Relative,
 }
 
 impl Mode {
-    fn from(mode: i64) -> Self {
+    pub(crate) fn decode(mode: i64) -> Option<Self> {
         match mode % 10 {
-            0 => Mode::Position,
-            1 => Mode::Immediate,
-            2 => Mode::Relative,
-            _ => panic!("Invalid mode {:?}", mode % 10),
+            0 => Some(Mode::Position),
+            1 => Some(Mode::Immediate),
+            2 => Some(Mode::Relative),
+            _ => None,
         }
     }
 
-    fn from3(mode: i64) -> (Self, Self, Self) {
-        (
-            Self::from(mode),
-            Self::from(mode / 10),
-            Self::from(mode / 100),
-        )
+    pub fn digit(&self) -> i64 {
+        match self {
+            Mode::Position => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2,
+        }
+    }
+
+    fn decode3(mode: i64) -> Option<[Self; 3]> {
+        Some([
+            Self::decode(mode)?,
+            Self::decode(mode / 10)?,
+            Self::decode(mode / 100)?,
+        ])
     }
 }
 
-#[derive(PartialEq)]
-enum Instruction {
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Instruction {
     Add,
     Multiply,
     Input,
@@ -49,43 +64,680 @@ enum Instruction {
 }
 
 impl Instruction {
-    fn from(value: i64) -> Self {
+    pub const ALL: [Instruction; 10] = [
+        Instruction::Add,
+        Instruction::Multiply,
+        Instruction::Input,
+        Instruction::Output,
+        Instruction::JumpIfTrue,
+        Instruction::JumpIfFalse,
+        Instruction::LessThan,
+        Instruction::Equals,
+        Instruction::ShiftBase,
+        Instruction::Halt,
+    ];
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|instruction| instruction.mnemonic() == mnemonic)
+    }
+
+    pub(crate) fn decode(value: i64) -> Option<Self> {
         match value % 100 {
-            1 => Instruction::Add,
-            2 => Instruction::Multiply,
-            3 => Instruction::Input,
-            4 => Instruction::Output,
-            5 => Instruction::JumpIfTrue,
-            6 => Instruction::JumpIfFalse,
-            7 => Instruction::LessThan,
-            8 => Instruction::Equals,
-            9 => Instruction::ShiftBase,
-            99 => Instruction::Halt,
-            _ => panic!("Invalid instruction {:?}", value % 100),
+            1 => Some(Instruction::Add),
+            2 => Some(Instruction::Multiply),
+            3 => Some(Instruction::Input),
+            4 => Some(Instruction::Output),
+            5 => Some(Instruction::JumpIfTrue),
+            6 => Some(Instruction::JumpIfFalse),
+            7 => Some(Instruction::LessThan),
+            8 => Some(Instruction::Equals),
+            9 => Some(Instruction::ShiftBase),
+            99 => Some(Instruction::Halt),
+            _ => None,
+        }
+    }
+
+    pub fn opcode(&self) -> i64 {
+        match self {
+            Instruction::Add => 1,
+            Instruction::Multiply => 2,
+            Instruction::Input => 3,
+            Instruction::Output => 4,
+            Instruction::JumpIfTrue => 5,
+            Instruction::JumpIfFalse => 6,
+            Instruction::LessThan => 7,
+            Instruction::Equals => 8,
+            Instruction::ShiftBase => 9,
+            Instruction::Halt => 99,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Add => "add",
+            Instruction::Multiply => "mul",
+            Instruction::Input => "in",
+            Instruction::Output => "out",
+            Instruction::JumpIfTrue => "jt",
+            Instruction::JumpIfFalse => "jf",
+            Instruction::LessThan => "lt",
+            Instruction::Equals => "eq",
+            Instruction::ShiftBase => "arb",
+            Instruction::Halt => "hlt",
+        }
+    }
+
+    // Number of parameters following the opcode
+    pub fn arity(&self) -> usize {
+        match self {
+            Instruction::Add | Instruction::Multiply => 3,
+            Instruction::LessThan | Instruction::Equals => 3,
+            Instruction::JumpIfTrue | Instruction::JumpIfFalse => 2,
+            Instruction::Input | Instruction::Output | Instruction::ShiftBase => 1,
+            Instruction::Halt => 0,
+        }
+    }
+
+    // Whether the last parameter is an address written to
+    pub fn writes(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Add
+                | Instruction::Multiply
+                | Instruction::Input
+                | Instruction::LessThan
+                | Instruction::Equals
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Param {
+    pub mode: Mode,
+    pub value: i64,
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            Mode::Position => write!(f, "[{}]", self.value),
+            Mode::Immediate => write!(f, "#{}", self.value),
+            Mode::Relative => write!(f, "rel[{:+}]", self.value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Op {
+    pub addr: usize,
+    pub instruction: Instruction,
+    pub params: Vec<Param>,
+}
+
+impl Op {
+    // Decodes the instruction at addr, returning None if the value there is
+    // not a valid opcode (or it would write in immediate mode)
+    pub fn decode(code: &[i64], addr: usize) -> Option<Self> {
+        Self::decode_with(|ptr| code.get(ptr).copied().unwrap_or(0), addr)
+    }
+
+    pub(crate) fn decode_with(at: impl Fn(usize) -> i64, addr: usize) -> Option<Self> {
+        let value = at(addr);
+        if value < 0 {
+            return None;
+        }
+        let instruction = Instruction::decode(value)?;
+        let modes = Mode::decode3(value / 100)?;
+        let params: Vec<_> = (0..instruction.arity())
+            .map(|i| Param {
+                mode: modes[i],
+                value: at(addr + 1 + i),
+            })
+            .collect();
+        if instruction.writes() && params.last().unwrap().mode == Mode::Immediate {
+            return None;
+        }
+        Some(Self {
+            addr,
+            instruction,
+            params,
+        })
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        1 + self.params.len()
+    }
+
+    pub fn encode(&self) -> Vec<i64> {
+        let modes = self
+            .params
+            .iter()
+            .rev()
+            .fold(0, |modes, param| modes * 10 + param.mode.digit());
+        std::iter::once(modes * 100 + self.instruction.opcode())
+            .chain(self.params.iter().map(|param| param.value))
+            .collect()
+    }
+
+    // Statically known jump target (only for immediate mode targets)
+    pub fn jump_target(&self) -> Option<usize> {
+        match self.instruction {
+            Instruction::JumpIfTrue | Instruction::JumpIfFalse => {
+                let target = self.params[1];
+                (target.mode == Mode::Immediate && target.value >= 0)
+                    .then_some(target.value as usize)
+            }
+            _ => None,
+        }
+    }
+
+    // Whether a jump is taken regardless of state (Some(true)), never taken
+    // (Some(false)), or depends on state (None)
+    pub fn jump_always(&self) -> Option<bool> {
+        let cond = self.params.first()?;
+        if cond.mode != Mode::Immediate {
+            return None;
+        }
+        match self.instruction {
+            Instruction::JumpIfTrue => Some(cond.value != 0),
+            Instruction::JumpIfFalse => Some(cond.value == 0),
+            _ => None,
+        }
+    }
+
+    // Whether execution can continue with the instruction following this one
+    pub fn falls_through(&self) -> bool {
+        match self.instruction {
+            Instruction::Halt => false,
+            Instruction::JumpIfTrue | Instruction::JumpIfFalse => self.jump_always() != Some(true),
+            _ => true,
+        }
+    }
+
+    // Constant written to the stack by `add #a, #b -> rel[..]` (e.g. a return address)
+    pub(crate) fn pushed_constant(&self) -> Option<i64> {
+        match (self.instruction, &self.params[..]) {
+            (Instruction::Add | Instruction::Multiply, [a, b, dest])
+                if a.mode == Mode::Immediate
+                    && b.mode == Mode::Immediate
+                    && dest.mode == Mode::Relative =>
+            {
+                if self.instruction == Instruction::Add {
+                    a.value.checked_add(b.value)
+                } else {
+                    a.value.checked_mul(b.value)
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.instruction.mnemonic())?;
+        let (sources, dest) = if self.instruction.writes() {
+            self.params.split_at(self.params.len() - 1)
+        } else {
+            (&self.params[..], &[][..])
+        };
+        for (i, param) in sources.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, param)?;
+        }
+        if let Some(dest) = dest.first() {
+            write!(f, " -> {}", dest)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Listing {
+    Code(Op),
+    Data { addr: usize, values: Vec<i64> },
+}
+
+impl Listing {
+    pub fn addr(&self) -> usize {
+        match self {
+            Listing::Code(op) => op.addr,
+            Listing::Data { addr, .. } => *addr,
+        }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listing::Code(op) => write!(f, "{:04}: {}", op.addr, op),
+            Listing::Data { addr, values } => {
+                write!(f, "{:04}: db ", addr)?;
+                for (i, value) in values.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { ", " }, value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Disassembly {
+    pub listings: Vec<Listing>,
+}
+
+impl Disassembly {
+    const DATA_PER_LINE: usize = 8;
+
+    pub fn new(code: &[i64]) -> Self {
+        let reachable = reachable_ops(code);
+        let mut listings = vec![];
+        let mut data: Vec<i64> = vec![];
+        let mut addr = 0;
+        while addr < code.len() {
+            // Ops with stray mode digits (e.g. 1104) are listed as data, since
+            // the listing could only assemble back to their canonical encoding
+            let op = reachable
+                .get(&addr)
+                .filter(|op| op.encode()[0] == code[addr]);
+            if let Some(op) = op {
+                if !data.is_empty() {
+                    listings.push(Listing::Data {
+                        addr: addr - data.len(),
+                        values: std::mem::take(&mut data),
+                    });
+                }
+                addr += op.len();
+                listings.push(Listing::Code(op.clone()));
+            } else {
+                data.push(code[addr]);
+                addr += 1;
+                if data.len() == Self::DATA_PER_LINE {
+                    listings.push(Listing::Data {
+                        addr: addr - data.len(),
+                        values: std::mem::take(&mut data),
+                    });
+                }
+            }
+        }
+        if !data.is_empty() {
+            listings.push(Listing::Data {
+                addr: addr - data.len(),
+                values: data,
+            });
+        }
+        Self { listings }
+    }
+
+    pub fn ops(&self) -> impl Iterator<Item = &Op> {
+        self.listings.iter().filter_map(|listing| match listing {
+            Listing::Code(op) => Some(op),
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for listing in &self.listings {
+            writeln!(f, "{}", listing)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn disassemble(code: &[i64]) -> Disassembly {
+    Disassembly::new(code)
+}
+
+// Walks the program from address 0, following fall through and statically
+// known jump targets. Code following an unconditional jump is only considered
+// reachable if the instruction before the jump pushed its address (i.e. the
+// jump is a call and the address is where it returns to).
+pub fn reachable_ops(code: &[i64]) -> BTreeMap<usize, Op> {
+    reachable_ops_from(code, &[0])
+}
+
+// As reachable_ops, but starting from each of the given addresses
+pub fn reachable_ops_from(code: &[i64], roots: &[usize]) -> BTreeMap<usize, Op> {
+    let mut ops: BTreeMap<usize, Op> = BTreeMap::new();
+    let mut covered = HashSet::new();
+    let mut tried = HashSet::new();
+    let mut todo = roots.to_vec();
+
+    loop {
+        while let Some(addr) = todo.pop() {
+            if addr >= code.len() || !tried.insert(addr) {
+                continue;
+            }
+            let Some(op) = Op::decode(code, addr) else {
+                continue;
+            };
+            if (addr..addr + op.len()).any(|a| covered.contains(&a)) {
+                continue;
+            }
+            covered.extend(addr..addr + op.len());
+
+            if op.falls_through() {
+                todo.push(addr + op.len());
+            }
+            if op.jump_always() != Some(false) {
+                todo.extend(op.jump_target());
+            }
+            ops.insert(addr, op);
+        }
+
+        // Resume after calls whose return address push has since been discovered
+        todo.extend(ops.values().filter_map(|op| {
+            let next = op.addr + op.len();
+            let call = op.instruction != Instruction::Halt
+                && !op.falls_through()
+                && !tried.contains(&next)
+                && ops
+                    .range(..op.addr)
+                    .next_back()
+                    .filter(|(addr, prev)| *addr + prev.len() == op.addr)
+                    .and_then(|(_, prev)| prev.pushed_constant())
+                    == Some(next as i64);
+            call.then_some(next)
+        }));
+        if todo.is_empty() {
+            break;
+        }
+    }
+
+    ops
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum IntcodeError {
+    Parse {
+        index: usize,
+        token: String,
+    },
+    BadOpcode {
+        value: i64,
+        ptr: usize,
+        relbase: i64,
+    },
+    BadMode {
+        value: i64,
+        ptr: usize,
+        relbase: i64,
+    },
+    NegativeAddress {
+        address: i64,
+        ptr: usize,
+        relbase: i64,
+    },
+    ImmediateWrite {
+        ptr: usize,
+        relbase: i64,
+    },
+    // Result, output or address too large for an i64
+    Overflow {
+        ptr: usize,
+        relbase: i64,
+    },
+    // Raised by an extension instruction
+    Extension {
+        message: String,
+        ptr: usize,
+        relbase: i64,
+    },
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntcodeError::Parse { index, token } => {
+                write!(f, "Invalid value {:?} at index {}", token, index)
+            }
+            IntcodeError::BadOpcode {
+                value,
+                ptr,
+                relbase,
+            } => write!(
+                f,
+                "Invalid instruction {} at {} (relbase {})",
+                value, ptr, relbase
+            ),
+            IntcodeError::BadMode {
+                value,
+                ptr,
+                relbase,
+            } => write!(
+                f,
+                "Invalid mode in {} at {} (relbase {})",
+                value, ptr, relbase
+            ),
+            IntcodeError::NegativeAddress {
+                address,
+                ptr,
+                relbase,
+            } => write!(
+                f,
+                "Negative address {} at {} (relbase {})",
+                address, ptr, relbase
+            ),
+            IntcodeError::ImmediateWrite { ptr, relbase } => write!(
+                f,
+                "Write in immediate mode at {} (relbase {})",
+                ptr, relbase
+            ),
+            IntcodeError::Overflow { ptr, relbase } => {
+                write!(f, "Overflow at {} (relbase {})", ptr, relbase)
+            }
+            IntcodeError::Extension {
+                message,
+                ptr,
+                relbase,
+            } => write!(f, "{} at {} (relbase {})", message, ptr, relbase),
+        }
+    }
+}
+
+impl std::error::Error for IntcodeError {}
+
+// Failure within an instruction, before it is tied to where it happened
+enum Fault {
+    BadOpcode(i64),
+    BadMode(i64),
+    NegativeAddress(i64),
+    ImmediateWrite,
+    // A write beyond the memory limit, which stops the run without an error
+    MemoryLimit(usize),
+    Overflow,
+    Extension(String),
+}
+
+impl Fault {
+    fn at(self, ptr: usize, relbase: i64) -> Result<RunState, IntcodeError> {
+        Err(match self {
+            Fault::BadOpcode(value) => IntcodeError::BadOpcode {
+                value,
+                ptr,
+                relbase,
+            },
+            Fault::BadMode(value) => IntcodeError::BadMode {
+                value,
+                ptr,
+                relbase,
+            },
+            Fault::NegativeAddress(address) => IntcodeError::NegativeAddress {
+                address,
+                ptr,
+                relbase,
+            },
+            Fault::ImmediateWrite => IntcodeError::ImmediateWrite { ptr, relbase },
+            Fault::MemoryLimit(address) => return Ok(RunState::OutOfMemory(address)),
+            Fault::Overflow => IntcodeError::Overflow { ptr, relbase },
+            Fault::Extension(message) => IntcodeError::Extension {
+                message,
+                ptr,
+                relbase,
+            },
+        })
+    }
+}
+
+pub trait Tracer {
+    // Lets the VM skip building events entirely when tracing is disabled
+    const ENABLED: bool = true;
+
+    fn trace(&mut self, event: &TraceEvent);
+}
+
+pub struct NoTrace;
+
+impl Tracer for NoTrace {
+    const ENABLED: bool = false;
+
+    fn trace(&mut self, _event: &TraceEvent) {}
+}
+
+impl Tracer for Vec<TraceEvent> {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.push(*event);
+    }
+}
+
+impl<T: Tracer> Tracer for &mut T {
+    const ENABLED: bool = T::ENABLED;
+
+    fn trace(&mut self, event: &TraceEvent) {
+        (**self).trace(event);
+    }
+}
+
+impl<A: Tracer, B: Tracer> Tracer for (A, B) {
+    const ENABLED: bool = A::ENABLED || B::ENABLED;
+
+    fn trace(&mut self, event: &TraceEvent) {
+        self.0.trace(event);
+        self.1.trace(event);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Operands {
+    None,
+    One(i64),
+    Two(i64, i64),
+    Three(i64, i64, i64),
+}
+
+// An executed instruction with its operands resolved
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TraceEvent {
+    pub addr: usize,
+    pub relbase: i64,
+    // None for an extension instruction
+    pub instruction: Option<Instruction>,
+    pub opcode: i64,
+    operands: Operands,
+    pub write: Option<(usize, i64)>,
+}
+
+impl TraceEvent {
+    pub fn operands(&self) -> Vec<i64> {
+        match self.operands {
+            Operands::None => vec![],
+            Operands::One(v1) => vec![v1],
+            Operands::Two(v1, v2) => vec![v1, v2],
+            Operands::Three(v1, v2, v3) => vec![v1, v2, v3],
+        }
+    }
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.instruction {
+            Some(instruction) => write!(f, "{:04}: {}", self.addr, instruction.mnemonic())?,
+            None => write!(f, "{:04}: op{}", self.addr, self.opcode)?,
+        }
+        for (i, value) in self.operands().iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, value)?;
         }
+        if let Some((addr, value)) = self.write {
+            write!(f, " -> [{}] = {}", addr, value)?;
+        }
+        Ok(())
     }
 }
 
+// Why a call to run returned
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RunState {
+    Halted,
+    NeedsInput,
+    Output(i64), // Only when halting after output
+    StepLimit,
+    OutOfFuel,
+    OutOfMemory(usize), // Address of the write that was refused
+    Exit(i64),          // Exit code given by an extension instruction
+}
+
 #[derive(Clone, Default)]
 pub struct Intcode {
-    code: Vec<i64>,
+    code: Memory,
     ptr: usize,
     relbase: i64,
     halt_after_output: bool,
+    compiled: Option<Arc<Compiled>>,
+    // Compiled ops this machine has since written over
+    stale: HashSet<usize>,
+    history: Option<Box<History>>,
+    // Instructions left to execute, if limited
+    fuel: Option<u64>,
+    // Lowest address the program may not write to, if limited
+    max_memory: Option<usize>,
+    instruction_set: Option<Arc<InstructionSet>>,
+    arithmetic: Arithmetic,
+    // Values too large for an i64 (with Arithmetic::Big), which read as 0
+    // through code_at
+    big: HashMap<usize, BigInt>,
 }
 
 impl Intcode {
     pub fn new(codestr: &str) -> Self {
-        let code: Vec<i64> = codestr
+        match Self::try_new(codestr) {
+            Ok(intcode) => intcode,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    pub fn try_new(codestr: &str) -> Result<Self, IntcodeError> {
+        let code = codestr
             .trim()
             .split(',')
-            .map(|s| s.parse().unwrap())
-            .collect();
-        Self {
-            code,
+            .enumerate()
+            .map(|(index, s)| {
+                s.trim().parse().map_err(|_| IntcodeError::Parse {
+                    index,
+                    token: s.to_string(),
+                })
+            })
+            .collect::<Result<Vec<i64>, _>>()?;
+        Ok(Self {
+            code: code.into(),
             ptr: 0,
             relbase: 0,
             halt_after_output: false,
+            ..Default::default()
+        })
+    }
+
+    pub(crate) fn from_parts(code: Memory, ptr: usize, relbase: i64) -> Self {
+        Self {
+            code,
+            ptr,
+            relbase,
+            halt_after_output: false,
+            ..Default::default()
         }
     }
 
@@ -93,92 +745,599 @@ impl Intcode {
         self.halt_after_output = value;
     }
 
+    pub fn halts_after_output(&self) -> bool {
+        self.halt_after_output
+    }
+
+    // Limits the number of instructions executed from now on (over any
+    // number of runs), after which runs stop with OutOfFuel
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    // Limits the addresses the program can write to, with a write at or
+    // beyond the limit stopping the run with OutOfMemory (before the
+    // instruction completes)
+    pub fn set_max_memory(&mut self, max_memory: Option<usize>) {
+        self.max_memory = max_memory;
+    }
+
+    pub fn max_memory(&self) -> Option<usize> {
+        self.max_memory
+    }
+
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         self.code.len()
     }
 
+    pub fn ptr(&self) -> usize {
+        self.ptr
+    }
+
+    pub fn relbase(&self) -> i64 {
+        self.relbase
+    }
+
+    pub fn disassemble(&self) -> Disassembly {
+        Disassembly::new(&self.code.to_vec())
+    }
+
+    pub fn op_at(&self, addr: usize) -> Option<Op> {
+        Op::decode_with(|ptr| self.code.get(ptr), addr)
+    }
+
+    // Pre-decodes the reachable instructions in the current memory (leaving
+    // any in the sparse pages to the interpreter) so they needn't be decoded
+    // every time they run. Clones share the result.
+    pub fn compile(&mut self) {
+        let code = self.code.to_vec();
+        let compiled = match &self.instruction_set {
+            // Anything else is left to the interpreter to reject (or run)
+            Some(isa) => Compiled::filtered(&code, |op| isa.supports(op)),
+            None => Compiled::new(&code),
+        };
+        self.compiled = Some(Arc::new(compiled));
+        self.stale.clear();
+    }
+
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    // Addresses holding values too large for an i64, with their values
+    pub fn big_values(&self) -> impl Iterator<Item = (usize, &BigInt)> {
+        self.big.iter().map(|(&addr, value)| (addr, value))
+    }
+
+    // Sets an address to a value at full precision
+    pub fn set_big(&mut self, addr: usize, value: BigInt) {
+        match value.to_i64() {
+            Some(value) => self.set_code(addr, value),
+            None => {
+                self.set_code(addr, 0);
+                self.big.insert(addr, value);
+            }
+        }
+    }
+
+    // Value at an address at full precision
+    pub fn big_at(&self, addr: usize) -> BigInt {
+        match self.big.get(&addr) {
+            Some(value) => value.clone(),
+            None => self.code_at(addr).into(),
+        }
+    }
+
+    // Restricts (or extends) the instructions the machine accepts
+    pub fn set_instruction_set(&mut self, instruction_set: InstructionSet) {
+        self.instruction_set = Some(Arc::new(instruction_set));
+        if self.compiled.is_some() {
+            self.compile();
+        }
+    }
+
+    pub fn instruction_set(&self) -> Option<&InstructionSet> {
+        self.instruction_set.as_deref()
+    }
+
+    pub fn compiled(&self) -> Option<&Compiled> {
+        self.compiled.as_deref()
+    }
+
+    #[inline]
     pub fn set_code(&mut self, position: usize, value: i64) {
-        if position >= self.code.len() {
-            self.code.resize(position + 10, 0);
+        if let Some(history) = &mut self.history {
+            history.write(
+                position,
+                self.code.get(position),
+                self.big.get(&position).cloned(),
+            );
+        }
+        self.overwrite(position, value);
+    }
+
+    // Writes a value without it being seen by the history
+    #[inline]
+    fn overwrite(&mut self, position: usize, value: i64) {
+        if !self.big.is_empty() {
+            self.big.remove(&position);
+        }
+        self.code.set(position, value);
+        if let Some(owner) = self.compiled.as_ref().and_then(|c| c.owner(position)) {
+            self.stale.insert(owner);
+        }
+    }
+
+    // Starts keeping an undo log so the machine can be stepped backwards,
+    // with a checkpoint every interval steps (keeping at most checkpoints)
+    pub fn record_history(&mut self, interval: u64, checkpoints: usize) {
+        self.history = Some(Box::new(History::new(interval, checkpoints)));
+    }
+
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_deref()
+    }
+
+    // Undoes the last instruction executed while recording history, or
+    // returns None if there's nothing (left) to undo
+    pub fn step_back(&mut self) -> Option<Undone> {
+        let history = self.history.as_mut()?;
+        if !history.is_logged() {
+            let (step, checkpoint, inputs) = history.rewind()?;
+            let target = history.steps();
+            let mut history = self.history.take().unwrap();
+            history.truncate(step);
+            *self = checkpoint;
+            self.history = Some(history);
+
+            let mut inputs = inputs.into_iter();
+            loop {
+                let steps = self.history.as_ref().map_or(target, |h| h.steps());
+                if steps >= target {
+                    break;
+                }
+                let state = self.run_for(|| inputs.next(), |_| {}, (target - steps) as usize);
+                if matches!(state, RunState::NeedsInput | RunState::Halted) {
+                    break;
+                }
+            }
+        }
+
+        let (undone, big) = self.history.as_mut()?.pop()?;
+        self.ptr = undone.ptr;
+        self.relbase = undone.relbase;
+        if let Some((addr, old)) = undone.write {
+            self.overwrite(addr, old);
+            if let Some(big) = big {
+                self.big.insert(addr, big);
+            }
+        }
+        Some(undone)
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.code
+    }
+
+    pub fn is_halted(&self) -> bool {
+        Instruction::decode(self.code_at(self.ptr)) == Some(Instruction::Halt)
+    }
+
+    pub fn run<I, O>(&mut self, input: I, output: O) -> RunState
+    where
+        I: FnMut() -> Option<i64>,
+        O: FnMut(i64),
+    {
+        self.run_for(input, output, usize::MAX)
+    }
+
+    pub fn run_for<I, O>(&mut self, input: I, output: O, max_steps: usize) -> RunState
+    where
+        I: FnMut() -> Option<i64>,
+        O: FnMut(i64),
+    {
+        match self.try_run_for(input, output, max_steps) {
+            Ok(state) => state,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    pub fn try_run<I, O>(&mut self, input: I, output: O) -> Result<RunState, IntcodeError>
+    where
+        I: FnMut() -> Option<i64>,
+        O: FnMut(i64),
+    {
+        self.try_run_for(input, output, usize::MAX)
+    }
+
+    pub fn try_run_for<I, O>(
+        &mut self,
+        input: I,
+        output: O,
+        max_steps: usize,
+    ) -> Result<RunState, IntcodeError>
+    where
+        I: FnMut() -> Option<i64>,
+        O: FnMut(i64),
+    {
+        self.try_run_traced(input, output, max_steps, &mut NoTrace)
+    }
+
+    pub fn run_traced<I, O, T>(&mut self, input: I, output: O, tracer: &mut T) -> RunState
+    where
+        I: FnMut() -> Option<i64>,
+        O: FnMut(i64),
+        T: Tracer,
+    {
+        match self.try_run_traced(input, output, usize::MAX, tracer) {
+            Ok(state) => state,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    // On failure the instruction pointer is left on the offending instruction
+    pub fn try_run_traced<I, O, T>(
+        &mut self,
+        input: I,
+        output: O,
+        max_steps: usize,
+        tracer: &mut T,
+    ) -> Result<RunState, IntcodeError>
+    where
+        I: FnMut() -> Option<i64>,
+        O: FnMut(i64),
+        T: Tracer,
+    {
+        if self.fuel.is_none()
+            && self.history.is_none()
+            && self.instruction_set.is_none()
+            && self.big.is_empty()
+        {
+            return self.run_plain(input, output, max_steps, tracer);
         }
-        self.code[position] = value;
+        self.run_checked(input, output, max_steps, tracer)
     }
 
-    pub fn run<I, O>(&mut self, mut input: I, mut output: O)
+    // The loop for any machine, checking fuel, history, the instruction set
+    // and big values at each instruction
+    fn run_checked<I, O, T>(
+        &mut self,
+        mut input: I,
+        mut output: O,
+        max_steps: usize,
+        tracer: &mut T,
+    ) -> Result<RunState, IntcodeError>
     where
         I: FnMut() -> Option<i64>,
         O: FnMut(i64),
+        T: Tracer,
     {
-        loop {
-            let (instruction, (m1, m2, m3)) = self.read_instruction();
-            match instruction {
-                Instruction::Add => {
-                    let (v1, v2) = self.read2(m1, m2);
-                    self.write(v1 + v2, m3);
+        let compiled = self.compiled.clone();
+        let isa = self.instruction_set.clone();
+        for _ in 0..max_steps {
+            let ptr = self.ptr;
+            if self.fuel == Some(0) {
+                return Ok(RunState::OutOfFuel);
+            }
+            if self.history.is_some() {
+                self.begin_history();
+            }
+            let op = compiled.as_ref().and_then(|c| c.op(ptr));
+            let lookup = isa.as_ref().map(|isa| isa.lookup(self.code_at(ptr)));
+            let mut result = match (op, lookup) {
+                _ if !self.big.is_empty() && self.reads_big() => {
+                    self.step_big(&mut input, &mut output, tracer)
                 }
-                Instruction::Multiply => {
-                    let (v1, v2) = self.read2(m1, m2);
-                    self.write(v1 * v2, m3);
+                (Some(op), _) if self.stale.is_empty() || !self.stale.contains(&ptr) => {
+                    self.step_compiled(op, &mut input, &mut output, tracer)
                 }
-                Instruction::Input => {
-                    if let Some(value) = input() {
-                        self.write(value, m1);
-                    } else {
-                        self.ptr -= 1; // Reset the pointer
-                        break;
-                    }
+                (_, None | Some(Lookup::Builtin)) => self.step(&mut input, &mut output, tracer),
+                (_, Some(Lookup::Extension(extension))) => self.step_extension(extension, tracer),
+                (_, Some(Lookup::BadOpcode)) => Err(Fault::BadOpcode(self.code_at(ptr))),
+                (_, Some(Lookup::BadMode)) => Err(Fault::BadMode(self.code_at(ptr))),
+            };
+            if matches!(result, Err(Fault::Overflow))
+                && self.arithmetic == Arithmetic::Big
+                && matches!(
+                    Instruction::decode(self.code_at(ptr)),
+                    Some(Instruction::Add | Instruction::Multiply)
+                )
+            {
+                // Nothing was written, so it can just be run again
+                self.ptr = ptr;
+                result = self.step_big(&mut input, &mut output, tracer);
+            }
+            let completed = matches!(result, Ok(None | Some(RunState::Output(_))));
+            if let Some(history) = &mut self.history {
+                history.end(completed, &self.code);
+            }
+            if let (Some(fuel), true) = (&mut self.fuel, completed) {
+                *fuel -= 1;
+            }
+            match result {
+                Ok(None) => {}
+                Ok(Some(state)) => return Ok(state),
+                Err(fault) => {
+                    self.ptr = ptr;
+                    return fault.at(ptr, self.relbase);
                 }
-                Instruction::Output => {
-                    let value = self.read(m1);
-                    output(value);
-                    if self.halt_after_output {
-                        break;
-                    }
+            }
+        }
+        Ok(RunState::StepLimit)
+    }
+
+    // The loop for a machine with no fuel limit, history, instruction set or
+    // big values, which has nothing to check between instructions
+    fn run_plain<I, O, T>(
+        &mut self,
+        mut input: I,
+        mut output: O,
+        max_steps: usize,
+        tracer: &mut T,
+    ) -> Result<RunState, IntcodeError>
+    where
+        I: FnMut() -> Option<i64>,
+        O: FnMut(i64),
+        T: Tracer,
+    {
+        let compiled = self.compiled.clone();
+        for step in 0..max_steps {
+            let ptr = self.ptr;
+            let result = match compiled.as_ref().and_then(|c| c.op(ptr)) {
+                Some(op) if self.stale.is_empty() || !self.stale.contains(&ptr) => {
+                    self.step_compiled(op, &mut input, &mut output, tracer)
                 }
-                Instruction::JumpIfTrue => {
-                    let (v1, v2) = self.read2(m1, m2);
-                    if v1 != 0 {
-                        assert!(v2 >= 0, "Pointer is negative");
-                        self.ptr = v2 as usize;
-                    }
+                _ => self.step(&mut input, &mut output, tracer),
+            };
+            match result {
+                Ok(None) => {}
+                Ok(Some(state)) => return Ok(state),
+                Err(Fault::Overflow) if self.arithmetic == Arithmetic::Big => {
+                    // Nothing was written, so it can be run again at full
+                    // precision
+                    self.ptr = ptr;
+                    return self.run_checked(input, output, max_steps - step, tracer);
                 }
-                Instruction::JumpIfFalse => {
-                    let (v1, v2) = self.read2(m1, m2);
-                    if v1 == 0 {
-                        assert!(v2 >= 0, "Pointer is negative");
-                        self.ptr = v2 as usize;
-                    }
+                Err(fault) => {
+                    self.ptr = ptr;
+                    return fault.at(ptr, self.relbase);
                 }
-                Instruction::LessThan => {
-                    let (v1, v2) = self.read2(m1, m2);
-                    self.write(if v1 < v2 { 1 } else { 0 }, m3);
+            }
+        }
+        Ok(RunState::StepLimit)
+    }
+
+    fn begin_history(&mut self) {
+        if self.history.as_ref().is_some_and(|h| h.checkpoint_due()) {
+            let mut history = self.history.take().unwrap();
+            history.checkpoint(self.clone());
+            self.history = Some(history);
+        }
+        // Extensions are logged too, without an instruction
+        let instruction = Instruction::decode(self.code_at(self.ptr));
+        if let Some(history) = &mut self.history {
+            history.begin(self.ptr, self.relbase, instruction);
+        }
+    }
+
+    // Executes a single builtin instruction (extensions and opcodes the
+    // instruction set rejects are dealt with by the caller), returning why to
+    // stop (if at all)
+    #[inline(always)]
+    fn step<I, O, T>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        tracer: &mut T,
+    ) -> Result<Option<RunState>, Fault>
+    where
+        I: FnMut() -> Option<i64>,
+        O: FnMut(i64),
+        T: Tracer,
+    {
+        let addr = self.ptr;
+        let relbase = self.relbase;
+        let (instruction, [m1, m2, m3]) = self.read_instruction()?;
+        let mut stop = None;
+        // Resolved operands and written value are only used when tracing
+        let (operands, write) = match instruction {
+            Instruction::Add => {
+                let (v1, v2) = self.read2(m1, m2)?;
+                let value = self.add(v1, v2)?;
+                let dest = self.write(value, m3)?;
+                (Operands::Two(v1, v2), Some((dest, value)))
+            }
+            Instruction::Multiply => {
+                let (v1, v2) = self.read2(m1, m2)?;
+                let value = self.mul(v1, v2)?;
+                let dest = self.write(value, m3)?;
+                (Operands::Two(v1, v2), Some((dest, value)))
+            }
+            Instruction::Input => {
+                if let Some(value) = input() {
+                    let dest = self.write(value, m1)?;
+                    (Operands::None, Some((dest, value)))
+                } else {
+                    self.ptr -= 1; // Reset the pointer
+                    return Ok(Some(RunState::NeedsInput));
                 }
-                Instruction::Equals => {
-                    let (v1, v2) = self.read2(m1, m2);
-                    self.write(if v1 == v2 { 1 } else { 0 }, m3);
+            }
+            Instruction::Output => {
+                let value = self.read(m1)?;
+                output(value);
+                if self.halt_after_output {
+                    stop = Some(RunState::Output(value));
                 }
-                Instruction::ShiftBase => {
-                    let v1 = self.read(m1);
-                    self.relbase += v1;
+                (Operands::One(value), None)
+            }
+            Instruction::JumpIfTrue => {
+                let (v1, v2) = self.read2(m1, m2)?;
+                if v1 != 0 {
+                    self.jump(v2)?;
                 }
-                Instruction::Halt => {
-                    break;
+                (Operands::Two(v1, v2), None)
+            }
+            Instruction::JumpIfFalse => {
+                let (v1, v2) = self.read2(m1, m2)?;
+                if v1 == 0 {
+                    self.jump(v2)?;
                 }
+                (Operands::Two(v1, v2), None)
+            }
+            Instruction::LessThan => {
+                let (v1, v2) = self.read2(m1, m2)?;
+                let value = if v1 < v2 { 1 } else { 0 };
+                let dest = self.write(value, m3)?;
+                (Operands::Two(v1, v2), Some((dest, value)))
+            }
+            Instruction::Equals => {
+                let (v1, v2) = self.read2(m1, m2)?;
+                let value = if v1 == v2 { 1 } else { 0 };
+                let dest = self.write(value, m3)?;
+                (Operands::Two(v1, v2), Some((dest, value)))
             }
+            Instruction::ShiftBase => {
+                let v1 = self.read(m1)?;
+                self.relbase = self.relbase.checked_add(v1).ok_or(Fault::Overflow)?;
+                (Operands::One(v1), None)
+            }
+            Instruction::Halt => {
+                self.ptr -= 1; // Stay halted if run again
+                stop = Some(RunState::Halted);
+                (Operands::None, None)
+            }
+        };
+        if T::ENABLED {
+            tracer.trace(&TraceEvent {
+                addr,
+                relbase,
+                instruction: Some(instruction),
+                opcode: instruction.opcode(),
+                operands,
+                write,
+            });
         }
+        Ok(stop)
     }
 
-    pub fn run_with_no_io(&mut self) {
-        self.run(|| None, |_| {});
+    // Same as step, but for an instruction decoded ahead of time
+    #[inline(always)]
+    fn step_compiled<I, O, T>(
+        &mut self,
+        op: CompiledOp,
+        input: &mut I,
+        output: &mut O,
+        tracer: &mut T,
+    ) -> Result<Option<RunState>, Fault>
+    where
+        I: FnMut() -> Option<i64>,
+        O: FnMut(i64),
+        T: Tracer,
+    {
+        let addr = self.ptr;
+        let relbase = self.relbase;
+        let [a1, a2, a3] = op.args;
+        self.ptr += op.len;
+        let mut stop = None;
+        let (operands, write) = match op.instruction {
+            Instruction::Add => {
+                let (v1, v2) = (self.load(a1)?, self.load(a2)?);
+                let value = self.add(v1, v2)?;
+                let dest = self.store(a3, value)?;
+                (Operands::Two(v1, v2), Some((dest, value)))
+            }
+            Instruction::Multiply => {
+                let (v1, v2) = (self.load(a1)?, self.load(a2)?);
+                let value = self.mul(v1, v2)?;
+                let dest = self.store(a3, value)?;
+                (Operands::Two(v1, v2), Some((dest, value)))
+            }
+            Instruction::Input => {
+                if let Some(value) = input() {
+                    let dest = self.store(a1, value)?;
+                    (Operands::None, Some((dest, value)))
+                } else {
+                    self.ptr = addr;
+                    return Ok(Some(RunState::NeedsInput));
+                }
+            }
+            Instruction::Output => {
+                let value = self.load(a1)?;
+                output(value);
+                if self.halt_after_output {
+                    stop = Some(RunState::Output(value));
+                }
+                (Operands::One(value), None)
+            }
+            Instruction::JumpIfTrue => {
+                let (v1, v2) = (self.load(a1)?, self.load(a2)?);
+                if v1 != 0 {
+                    self.jump(v2)?;
+                }
+                (Operands::Two(v1, v2), None)
+            }
+            Instruction::JumpIfFalse => {
+                let (v1, v2) = (self.load(a1)?, self.load(a2)?);
+                if v1 == 0 {
+                    self.jump(v2)?;
+                }
+                (Operands::Two(v1, v2), None)
+            }
+            Instruction::LessThan => {
+                let (v1, v2) = (self.load(a1)?, self.load(a2)?);
+                let value = if v1 < v2 { 1 } else { 0 };
+                let dest = self.store(a3, value)?;
+                (Operands::Two(v1, v2), Some((dest, value)))
+            }
+            Instruction::Equals => {
+                let (v1, v2) = (self.load(a1)?, self.load(a2)?);
+                let value = if v1 == v2 { 1 } else { 0 };
+                let dest = self.store(a3, value)?;
+                (Operands::Two(v1, v2), Some((dest, value)))
+            }
+            Instruction::ShiftBase => {
+                let v1 = self.load(a1)?;
+                self.relbase = self.relbase.checked_add(v1).ok_or(Fault::Overflow)?;
+                (Operands::One(v1), None)
+            }
+            Instruction::Halt => {
+                self.ptr = addr;
+                stop = Some(RunState::Halted);
+                (Operands::None, None)
+            }
+        };
+        if T::ENABLED {
+            tracer.trace(&TraceEvent {
+                addr,
+                relbase,
+                instruction: Some(op.instruction),
+                opcode: op.instruction.opcode(),
+                operands,
+                write,
+            });
+        }
+        Ok(stop)
+    }
+
+    pub fn run_with_no_io(&mut self) -> RunState {
+        self.run(|| None, |_| {})
     }
 
-    pub fn run_with_io(&mut self, io: &mut dyn IntcodeIO) {
+    pub fn run_with_io(&mut self, io: &mut dyn IntcodeIO) -> RunState {
         let m = Mutex::new(io);
         self.run(
             || m.lock().unwrap().input(),
             |v| m.lock().unwrap().output(v),
-        );
+        )
     }
 
     pub fn run_simple<T: Copy + NumCast>(&mut self, input: &[T]) -> Option<i64> {
@@ -199,64 +1358,325 @@ impl Intcode {
     }
 
     pub fn run_ascii_and_capture(&mut self, input: &str) -> String {
-        let input: Vec<_> = input.chars().map(|c| c as i64).collect();
         let mut output = String::new();
-        self.run(input_fn(&input), |v| output.push((v as u8) as char));
+        self.run_ascii_with(input, |v| output.push((v as u8) as char));
         output
     }
 
-    pub fn run_async(&mut self, input: &Receiver<i64>, output: &Sender<i64>) {
+    // The text output, and the last value output if it's outside the ASCII
+    // range (as answers usually are). Other values outside the range are
+    // left out of the text.
+    pub fn run_ascii_and_answer(&mut self, input: &str) -> (String, Option<i64>) {
+        let mut output = String::new();
+        let mut last = None;
+        self.run_ascii_with(input, |v| {
+            if (0..128).contains(&v) {
+                output.push((v as u8) as char);
+            }
+            last = Some(v);
+        });
+        (output, last.filter(|v| !(0..128).contains(v)))
+    }
+
+    fn run_ascii_with<O: FnMut(i64)>(&mut self, input: &str, output: O) -> RunState {
+        let input: Vec<_> = input.chars().map(|c| c as i64).collect();
+        self.run(input_fn(&input), output)
+    }
+
+    pub fn run_async(&mut self, input: &Receiver<i64>, output: &Sender<i64>) -> RunState {
         self.run(
             || input.recv().ok(),
             |v| output.send(v).expect("Failure sending output"),
-        );
+        )
     }
 
-    fn code_ati(&self, ptr: i64) -> i64 {
-        assert!(ptr >= 0, "Pointer is negative");
-        self.code_at(ptr as usize)
+    fn code_ati(&self, ptr: i64) -> Result<i64, Fault> {
+        if ptr < 0 {
+            return Err(Fault::NegativeAddress(ptr));
+        }
+        Ok(self.code_at(ptr as usize))
     }
 
+    #[inline]
     pub fn code_at(&self, ptr: usize) -> i64 {
-        if ptr < self.code.len() {
-            self.code[ptr]
-        } else {
-            0
+        self.code.get(ptr)
+    }
+
+    // Executes an instruction at full precision, which is needed when it reads
+    // values too large for an i64. Input and output are still i64, so
+    // outputting (or jumping to) anything larger is an overflow. Values too
+    // large for an i64 are traced as 0, as code_at reads them.
+    fn step_big<I, O, T>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        tracer: &mut T,
+    ) -> Result<Option<RunState>, Fault>
+    where
+        I: FnMut() -> Option<i64>,
+        O: FnMut(i64),
+        T: Tracer,
+    {
+        let addr = self.ptr;
+        let relbase = self.relbase;
+        let small = |value: BigInt| value.to_i64().ok_or(Fault::Overflow);
+        let traced = |value: &BigInt| value.to_i64().unwrap_or(0);
+        let (instruction, [m1, m2, m3]) = self.read_instruction()?;
+        let mut stop = None;
+        let (operands, write) = match instruction {
+            Instruction::Add
+            | Instruction::Multiply
+            | Instruction::LessThan
+            | Instruction::Equals => {
+                let (v1, v2) = (self.read_big(m1)?, self.read_big(m2)?);
+                let operands = Operands::Two(traced(&v1), traced(&v2));
+                let value = match instruction {
+                    Instruction::Add => v1 + v2,
+                    Instruction::Multiply => v1 * v2,
+                    Instruction::LessThan => BigInt::from((v1 < v2) as i64),
+                    _ => BigInt::from((v1 == v2) as i64),
+                };
+                let dest = self.write(traced(&value), m3)?;
+                if value.to_i64().is_none() {
+                    self.big.insert(dest, value);
+                }
+                (operands, Some((dest, self.code_at(dest))))
+            }
+            Instruction::Input => match input() {
+                Some(value) => {
+                    let dest = self.write(value, m1)?;
+                    (Operands::None, Some((dest, value)))
+                }
+                None => {
+                    self.ptr -= 1;
+                    return Ok(Some(RunState::NeedsInput));
+                }
+            },
+            Instruction::Output => {
+                let value = small(self.read_big(m1)?)?;
+                output(value);
+                if self.halt_after_output {
+                    stop = Some(RunState::Output(value));
+                }
+                (Operands::One(value), None)
+            }
+            Instruction::JumpIfTrue | Instruction::JumpIfFalse => {
+                let (v1, v2) = (self.read_big(m1)?, self.read_big(m2)?);
+                let zero = v1 == BigInt::from(0);
+                if zero == (instruction == Instruction::JumpIfFalse) {
+                    self.jump(small(v2.clone())?)?;
+                }
+                (Operands::Two(traced(&v1), traced(&v2)), None)
+            }
+            Instruction::ShiftBase => {
+                let v1 = small(self.read_big(m1)?)?;
+                self.relbase = self.relbase.checked_add(v1).ok_or(Fault::Overflow)?;
+                (Operands::One(v1), None)
+            }
+            Instruction::Halt => {
+                self.ptr -= 1;
+                stop = Some(RunState::Halted);
+                (Operands::None, None)
+            }
+        };
+        if T::ENABLED {
+            tracer.trace(&TraceEvent {
+                addr,
+                relbase,
+                instruction: Some(instruction),
+                opcode: instruction.opcode(),
+                operands,
+                write,
+            });
+        }
+        Ok(stop)
+    }
+
+    // Whether the (builtin) instruction at ptr reads a value only held at
+    // full precision, so has to be run by step_big
+    fn reads_big(&self) -> bool {
+        let value = self.code_at(self.ptr);
+        if let Some(isa) = &self.instruction_set {
+            if !matches!(isa.lookup(value), Lookup::Builtin) {
+                return false;
+            }
+        }
+        let Some(op) = Op::decode_with(|addr| self.code_at(addr), self.ptr) else {
+            return false;
+        };
+        let reads = op.params.len() - op.instruction.writes() as usize;
+        op.params[..reads].iter().any(|param| {
+            let addr = match param.mode {
+                Mode::Position => Some(param.value),
+                Mode::Immediate => None,
+                Mode::Relative => param.value.checked_add(self.relbase),
+            };
+            addr.and_then(|addr| usize::try_from(addr).ok())
+                .is_some_and(|addr| self.big.contains_key(&addr))
+        })
+    }
+
+    fn read_big(&mut self, mode: Mode) -> Result<BigInt, Fault> {
+        let addr = match mode {
+            Mode::Position => self.code_at(self.ptr),
+            Mode::Immediate => self.ptr as i64,
+            Mode::Relative => self.relative(self.code_at(self.ptr))?,
+        };
+        self.ptr += 1;
+        if addr < 0 {
+            return Err(Fault::NegativeAddress(addr));
+        }
+        Ok(self.big_at(addr as usize))
+    }
+
+    #[inline(always)]
+    fn add(&self, v1: i64, v2: i64) -> Result<i64, Fault> {
+        match self.arithmetic {
+            Arithmetic::Wrapping => Ok(v1.wrapping_add(v2)),
+            Arithmetic::Checked | Arithmetic::Big => v1.checked_add(v2).ok_or(Fault::Overflow),
+        }
+    }
+
+    #[inline(always)]
+    fn mul(&self, v1: i64, v2: i64) -> Result<i64, Fault> {
+        match self.arithmetic {
+            Arithmetic::Wrapping => Ok(v1.wrapping_mul(v2)),
+            Arithmetic::Checked | Arithmetic::Big => v1.checked_mul(v2).ok_or(Fault::Overflow),
+        }
+    }
+
+    fn step_extension<T: Tracer>(
+        &mut self,
+        extension: &Extension,
+        tracer: &mut T,
+    ) -> Result<Option<RunState>, Fault> {
+        let addr = self.ptr;
+        let relbase = self.relbase;
+        let value = self.code_at(addr);
+        let mode =
+            |i: usize| Mode::decode(value / 10_i64.pow(i as u32 + 2)).ok_or(Fault::BadMode(value));
+        self.ptr += 1;
+        let reads = extension.params - extension.writes as usize;
+        let mut values = Vec::with_capacity(reads);
+        for i in 0..reads {
+            values.push(self.read(mode(i)?)?);
+        }
+        let write = match extension.run(&values) {
+            Effect::Write(value) if extension.writes => {
+                Some((self.write(value, mode(reads)?)?, value))
+            }
+            Effect::Continue | Effect::Write(_) => {
+                self.ptr += extension.params - reads;
+                None
+            }
+            Effect::Exit(code) => {
+                self.ptr = addr; // Stay stopped if run again
+                return Ok(Some(RunState::Exit(code)));
+            }
+            Effect::Fail(message) => return Err(Fault::Extension(message)),
+        };
+        if T::ENABLED {
+            let operands = match values[..] {
+                [] => Operands::None,
+                [v1] => Operands::One(v1),
+                [v1, v2] => Operands::Two(v1, v2),
+                [v1, v2, v3, ..] => Operands::Three(v1, v2, v3),
+            };
+            tracer.trace(&TraceEvent {
+                addr,
+                relbase,
+                instruction: None,
+                opcode: extension.opcode,
+                operands,
+                write,
+            });
         }
+        Ok(None)
     }
 
-    fn read_instruction(&mut self) -> (Instruction, (Mode, Mode, Mode)) {
+    fn read_instruction(&mut self) -> Result<(Instruction, [Mode; 3]), Fault> {
         let value = self.code_at(self.ptr);
         self.ptr += 1;
-        (Instruction::from(value), Mode::from3(value / 100))
+        let instruction = Instruction::decode(value).ok_or(Fault::BadOpcode(value))?;
+        let modes = Mode::decode3(value / 100).ok_or(Fault::BadMode(value))?;
+        Ok((instruction, modes))
     }
 
-    fn read(&mut self, mode: Mode) -> i64 {
+    fn read(&mut self, mode: Mode) -> Result<i64, Fault> {
         let value = self.code_at(self.ptr);
         self.ptr += 1;
         match mode {
             Mode::Position => self.code_ati(value),
-            Mode::Immediate => value,
-            Mode::Relative => self.code_ati(value + self.relbase),
+            Mode::Immediate => Ok(value),
+            Mode::Relative => self.code_ati(self.relative(value)?),
+        }
+    }
+
+    #[inline(always)]
+    fn load(&self, arg: Arg) -> Result<i64, Fault> {
+        match arg {
+            Arg::Immediate(value) => Ok(value),
+            Arg::Position(addr) => Ok(self.code_at(addr)),
+            Arg::Relative(offset) => self.code_ati(self.relative(offset)?),
         }
     }
 
-    fn read2(&mut self, m1: Mode, m2: Mode) -> (i64, i64) {
-        let v1 = self.read(m1);
-        let v2 = self.read(m2);
-        (v1, v2)
+    #[inline(always)]
+    fn store(&mut self, arg: Arg, value: i64) -> Result<usize, Fault> {
+        let addr = match arg {
+            Arg::Position(addr) => addr,
+            Arg::Relative(offset) => {
+                let addr = self.relative(offset)?;
+                if addr < 0 {
+                    return Err(Fault::NegativeAddress(addr));
+                }
+                addr as usize
+            }
+            Arg::Immediate(_) => return Err(Fault::ImmediateWrite),
+        };
+        if self.max_memory.is_some_and(|max| addr >= max) {
+            return Err(Fault::MemoryLimit(addr));
+        }
+        self.set_code(addr, value);
+        Ok(addr)
     }
 
-    fn write(&mut self, value: i64, mode: Mode) {
+    // Address of a relative mode parameter
+    #[inline(always)]
+    fn relative(&self, offset: i64) -> Result<i64, Fault> {
+        offset.checked_add(self.relbase).ok_or(Fault::Overflow)
+    }
+
+    fn read2(&mut self, m1: Mode, m2: Mode) -> Result<(i64, i64), Fault> {
+        let v1 = self.read(m1)?;
+        let v2 = self.read(m2)?;
+        Ok((v1, v2))
+    }
+
+    fn write(&mut self, value: i64, mode: Mode) -> Result<usize, Fault> {
         let ptr = self.code_at(self.ptr);
         let ptr = match mode {
             Mode::Position => ptr,
-            Mode::Immediate => ptr,
-            Mode::Relative => ptr + self.relbase,
+            Mode::Immediate => return Err(Fault::ImmediateWrite),
+            Mode::Relative => self.relative(ptr)?,
         };
-        let _ = self.code_ati(ptr);
+        if ptr < 0 {
+            return Err(Fault::NegativeAddress(ptr));
+        }
+        if self.max_memory.is_some_and(|max| ptr as usize >= max) {
+            return Err(Fault::MemoryLimit(ptr as usize));
+        }
         self.set_code(ptr as usize, value);
         self.ptr += 1;
+        Ok(ptr as usize)
+    }
+
+    fn jump(&mut self, target: i64) -> Result<(), Fault> {
+        if target < 0 {
+            return Err(Fault::NegativeAddress(target));
+        }
+        self.ptr = target as usize;
+        Ok(())
     }
 }
 
@@ -324,4 +1744,250 @@ mod tests {
         assert_eq!(intcode.run_simple(&[19132]), None);
         assert_eq!(intcode.code, [1002, 4, 3, 4, 99]);
     }
+
+    #[test]
+    fn test_run_state() {
+        let mut intcode = Intcode::new("3,9,4,9,3,9,4,9,99,0");
+        assert!(!intcode.is_halted());
+        assert_eq!(intcode.run(input_fn(&[1]), |_| {}), RunState::NeedsInput);
+        assert_eq!(intcode.ptr(), 4);
+        assert_eq!(intcode.run(input_fn(&[2]), |_| {}), RunState::Halted);
+        assert!(intcode.is_halted());
+        assert_eq!(intcode.run(|| None, |_| {}), RunState::Halted);
+    }
+
+    #[test]
+    fn test_run_state_output() {
+        let mut intcode = Intcode::new("104,1,104,2,99");
+        intcode.halt_after_output(true);
+        assert_eq!(intcode.run_with_no_io(), RunState::Output(1));
+        assert_eq!(intcode.run_with_no_io(), RunState::Output(2));
+        assert_eq!(intcode.run_with_no_io(), RunState::Halted);
+    }
+
+    #[test]
+    fn test_run_state_step_limit() {
+        let mut intcode = Intcode::new("1105,1,0");
+        assert_eq!(intcode.run_for(|| None, |_| {}, 100), RunState::StepLimit);
+        assert_eq!(intcode.ptr(), 0);
+    }
+
+    #[test]
+    fn test_run_state_out_of_fuel() {
+        let mut intcode = Intcode::new("1105,1,0");
+        intcode.set_fuel(Some(100));
+        assert_eq!(intcode.run_for(|| None, |_| {}, 60), RunState::StepLimit);
+        assert_eq!(intcode.fuel(), Some(40));
+        assert_eq!(intcode.run_with_no_io(), RunState::OutOfFuel);
+        assert_eq!(intcode.fuel(), Some(0));
+
+        intcode.set_fuel(Some(1));
+        assert_eq!(intcode.run_with_no_io(), RunState::OutOfFuel);
+    }
+
+    #[test]
+    fn test_run_state_out_of_memory() {
+        // Writes its input ever further on, moving the relative base
+        let mut intcode = Intcode::new("109,1000,203,0,1105,1,0");
+        intcode.set_max_memory(Some(2500));
+        assert_eq!(intcode.run(|| Some(1), |_| {}), RunState::OutOfMemory(3000));
+        assert_eq!(intcode.ptr(), 2);
+        assert_eq!(intcode.code_at(2000), 1);
+        assert_eq!(intcode.len(), 2001);
+    }
+
+    #[test]
+    fn test_try_new_parse_error() {
+        assert_eq!(
+            Intcode::try_new("1,2,x,4").err(),
+            Some(IntcodeError::Parse {
+                index: 2,
+                token: "x".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_try_run_bad_opcode() {
+        let mut intcode = Intcode::new("109,5,1,0,0,0,42");
+        let result = intcode.try_run(|| None, |_| {});
+        assert_eq!(
+            result,
+            Err(IntcodeError::BadOpcode {
+                value: 42,
+                ptr: 6,
+                relbase: 5
+            })
+        );
+        assert_eq!(intcode.ptr(), 6);
+    }
+
+    #[test]
+    fn test_try_run_bad_mode() {
+        let mut intcode = Intcode::new("301,0,0,0,99");
+        let result = intcode.try_run(|| None, |_| {});
+        assert_eq!(
+            result,
+            Err(IntcodeError::BadMode {
+                value: 301,
+                ptr: 0,
+                relbase: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_try_run_negative_address() {
+        let mut intcode = Intcode::new("4,-3,99");
+        let result = intcode.try_run(|| None, |_| {});
+        assert_eq!(
+            result,
+            Err(IntcodeError::NegativeAddress {
+                address: -3,
+                ptr: 0,
+                relbase: 0
+            })
+        );
+
+        let mut intcode = Intcode::new("1105,1,-1");
+        let result = intcode.try_run(|| None, |_| {});
+        assert_eq!(
+            result,
+            Err(IntcodeError::NegativeAddress {
+                address: -1,
+                ptr: 0,
+                relbase: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_try_run_immediate_write() {
+        let mut intcode = Intcode::new("104,7,11101,1,1,0,99");
+        let mut output = vec![];
+        let result = intcode.try_run(|| None, |v| output.push(v));
+        assert_eq!(output, [7]);
+        assert_eq!(
+            result,
+            Err(IntcodeError::ImmediateWrite { ptr: 2, relbase: 0 })
+        );
+    }
+
+    #[test]
+    fn test_try_run_relbase_overflow() {
+        let mut intcode = Intcode::new("109,9223372036854775807,109,1,99");
+        assert_eq!(
+            intcode.try_run(|| None, |_| {}),
+            Err(IntcodeError::Overflow {
+                ptr: 2,
+                relbase: i64::MAX
+            })
+        );
+
+        let mut intcode = Intcode::new("109,1,204,9223372036854775807,99");
+        assert_eq!(
+            intcode.try_run(|| None, |_| {}),
+            Err(IntcodeError::Overflow { ptr: 2, relbase: 1 })
+        );
+    }
+
+    #[test]
+    fn test_run_intcode_assembled_factorial() {
+        let code = crate::assemble(
+            "
+                    in -> [n]
+            loop:   mul [acc], [n] -> [acc]
+                    add [n], #-1 -> [n]
+                    jt [n], #loop
+                    out [acc]
+                    hlt
+            acc:    db 1
+            n:      ds 1
+            ",
+        )
+        .unwrap();
+        let mut intcode = Intcode::new(&code);
+        assert_eq!(intcode.run_simple(&[5]), Some(120));
+    }
+
+    #[test]
+    fn test_run_intcode_assembled_ascii() {
+        let code = crate::assemble(
+            "
+                    arb #msg
+            loop:   jf rel[0], #done
+                    out rel[0]
+                    arb #1
+                    jt #1, #loop
+            done:   hlt
+            msg:    db \"Hello\\n\", 0
+            ",
+        )
+        .unwrap();
+        let mut intcode = Intcode::new(&code);
+        assert_eq!(intcode.run_ascii_and_capture(""), "Hello\n");
+    }
+
+    #[test]
+    fn test_run_ascii_and_answer() {
+        let mut intcode = Intcode::new("104,72,104,500,104,105,104,1000,99");
+        assert_eq!(
+            intcode.run_ascii_and_answer(""),
+            ("Hi".to_string(), Some(1000))
+        );
+        let mut intcode = Intcode::new("104,72,104,105,99");
+        assert_eq!(intcode.run_ascii_and_answer(""), ("Hi".to_string(), None));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let intcode = Intcode::new("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99");
+        assert_eq!(
+            intcode.disassemble().to_string(),
+            "0000: arb #1
+0002: out rel[-1]
+0004: add [100], #1 -> [100]
+0008: eq [100], #16 -> [101]
+0012: jf [101], #0
+0015: hlt
+"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_data_after_jump() {
+        let intcode = Intcode::new("1105,1,4,42,99");
+        assert_eq!(
+            intcode.disassemble().to_string(),
+            "0000: jt #1, #4
+0003: db 42
+0004: hlt
+"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_call_and_return() {
+        let intcode = Intcode::new("21101,0,7,0,1105,1,9,99,5,2105,1,0");
+        assert_eq!(
+            intcode.disassemble().to_string(),
+            "0000: add #0, #7 -> rel[+0]
+0004: jt #1, #9
+0007: hlt
+0008: db 5
+0009: jt #1, rel[+0]
+"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_stray_modes() {
+        // Mode digits beyond an instruction's parameters are ignored when
+        // running, but listing them as data keeps the round trip exact
+        let code = "1104,5,10099";
+        assert_eq!(Intcode::new(code).run_simplen::<i64>(&[]), [5]);
+        let listing = Intcode::new(code).disassemble().to_string();
+        assert_eq!(listing, "0000: db 1104, 5, 10099\n");
+        assert_eq!(crate::assemble(&listing), Ok(code.to_string()));
+    }
 }
@@ -6,21 +6,63 @@ use std::cmp::PartialOrd;
 use std::fmt::Debug;
 use std::fmt::Display;
 
+mod adventure;
+mod arithmetic;
+mod ascii;
+mod assembler;
+mod cfg;
+mod compiler;
+mod compress;
+mod debugger;
+mod decompiler;
 mod digits;
 mod direction;
+mod fuzz;
 mod graph;
+mod history;
+mod instruction_set;
 mod intcode;
 mod line_segment;
 mod maps;
-
+mod memory;
+mod network;
+mod profiler;
+mod recording;
+mod runtime;
+mod snapshot;
+mod springdroid;
+mod springscript;
+mod watch;
+
+pub use adventure::*;
 #[allow(unused_imports)]
 pub use aoc_parse::{parser, prelude::*, ParseIter};
+pub use arithmetic::*;
+pub use ascii::*;
+pub use assembler::*;
+pub use cfg::*;
+pub use compiler::*;
+pub use compress::*;
+pub use debugger::*;
+pub use decompiler::*;
 pub use digits::*;
 pub use direction::*;
+pub use fuzz::*;
 pub use graph::*;
+pub use history::*;
+pub use instruction_set::*;
 pub use intcode::*;
 pub use line_segment::*;
 pub use maps::*;
+pub use memory::*;
+pub use network::*;
+pub use profiler::*;
+pub use recording::*;
+pub use runtime::*;
+pub use snapshot::*;
+pub use springdroid::*;
+pub use springscript::*;
+pub use watch::*;
 
 #[allow(dead_code)]
 pub fn name_to_digit(s: &str) -> Option<u32> {
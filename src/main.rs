@@ -1,5 +1,5 @@
 extern crate advent_of_code;
-use advent_of_code::template::commands::{all, download, read, scaffold, solve, time};
+use advent_of_code::template::commands::{all, debug, download, play, read, scaffold, solve, time};
 use args::{parse, AppArguments};
 
 #[cfg(feature = "today")]
@@ -37,6 +37,12 @@ mod args {
             day: Option<Day>,
             store: bool,
         },
+        Debug {
+            program: String,
+        },
+        Play {
+            program: String,
+        },
         #[cfg(feature = "today")]
         Today,
     }
@@ -58,6 +64,12 @@ mod args {
                     store,
                 }
             }
+            Some("debug") => AppArguments::Debug {
+                program: args.free_from_str()?,
+            },
+            Some("play") => AppArguments::Play {
+                program: args.free_from_str()?,
+            },
             Some("download") => AppArguments::Download {
                 day: args.free_from_str()?,
             },
@@ -105,6 +117,8 @@ fn main() {
         Ok(args) => match args {
             AppArguments::All { release } => all::handle(release),
             AppArguments::Time { day, all, store } => time::handle(day, all, store),
+            AppArguments::Debug { program } => debug::handle(&program),
+            AppArguments::Play { program } => play::handle(&program),
             AppArguments::Download { day } => download::handle(day),
             AppArguments::Read { day } => read::handle(day),
             AppArguments::Scaffold { day, download } => {
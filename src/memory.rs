@@ -0,0 +1,292 @@
+use hashbrown::HashMap;
+use itertools::Itertools;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Arc;
+
+const PAGE_BITS: usize = 9;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+const PAGE_MASK: usize = PAGE_SIZE - 1;
+// Pages past this index are kept in a map so a stray write to a huge
+// address doesn't allocate a page table to reach it
+const DENSE_PAGES: usize = 1 << 12;
+
+type Page = [i64; PAGE_SIZE];
+
+// An address whose value differs between two memories
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Change {
+    pub addr: usize,
+    pub old: i64,
+    pub new: i64,
+}
+
+// Intcode memory: fixed size pages shared between clones and only copied
+// when one of the clones writes to them. Cloning just bumps a refcount.
+#[derive(Clone, Default)]
+pub struct Memory {
+    pages: Arc<Vec<Option<Arc<Page>>>>,
+    sparse: Arc<HashMap<usize, Arc<Page>>>,
+    len: usize,
+    // One past the highest address written below the sparse pages
+    dense_len: usize,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // One past the highest address ever written
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // As above, ignoring the sparse pages (so it's the length of to_vec)
+    pub fn dense_len(&self) -> usize {
+        self.dense_len
+    }
+
+    #[inline]
+    pub fn get(&self, addr: usize) -> i64 {
+        self.page(addr >> PAGE_BITS)
+            .map_or(0, |page| page[addr & PAGE_MASK])
+    }
+
+    fn page(&self, index: usize) -> Option<&Arc<Page>> {
+        if index < DENSE_PAGES {
+            self.pages.get(index).and_then(Option::as_ref)
+        } else {
+            self.sparse.get(&index)
+        }
+    }
+
+    #[inline]
+    pub fn set(&mut self, addr: usize, value: i64) {
+        self.page_mut(addr >> PAGE_BITS)[addr & PAGE_MASK] = value;
+        if addr >= self.len {
+            self.len = addr + 1;
+        }
+        if addr >= self.dense_len && addr >> PAGE_BITS < DENSE_PAGES {
+            self.dense_len = addr + 1;
+        }
+    }
+
+    fn page_mut(&mut self, index: usize) -> &mut Page {
+        let page = if index < DENSE_PAGES {
+            let pages = Arc::make_mut(&mut self.pages);
+            if index >= pages.len() {
+                pages.resize(index + 1, None);
+            }
+            pages[index].get_or_insert_with(|| Arc::new([0; PAGE_SIZE]))
+        } else {
+            Arc::make_mut(&mut self.sparse)
+                .entry(index)
+                .or_insert_with(|| Arc::new([0; PAGE_SIZE]))
+        };
+        Arc::make_mut(page)
+    }
+
+    // Values up to the dense length, so not including anything written to
+    // the sparse pages (which would take too long to reach)
+    pub fn iter(&self) -> impl Iterator<Item = i64> + '_ {
+        (0..self.dense_len).map(|addr| self.get(addr))
+    }
+
+    pub fn to_vec(&self) -> Vec<i64> {
+        self.iter().collect()
+    }
+
+    // Pages that have been written to, by start address in order
+    pub fn pages(&self) -> impl Iterator<Item = (usize, &[i64])> + '_ {
+        let dense = self
+            .pages
+            .iter()
+            .enumerate()
+            .filter_map(|(index, page)| Some((index, page.as_ref()?)));
+        let sparse = self.sparse.iter().sorted_by_key(|(&index, _)| index);
+        dense
+            .chain(sparse.map(|(&index, page)| (index, page)))
+            .map(|(index, page)| (index << PAGE_BITS, &page[..]))
+    }
+
+    // Number of pages this memory shares with other (i.e. not yet copied
+    // since one was cloned from the other)
+    pub fn shared_pages(&self, other: &Memory) -> usize {
+        let dense = self
+            .pages
+            .iter()
+            .zip(other.pages.iter())
+            .filter(|(a, b)| matches!((a, b), (Some(a), Some(b)) if Arc::ptr_eq(a, b)))
+            .count();
+        let sparse = self
+            .sparse
+            .iter()
+            .filter(|(i, a)| other.sparse.get(*i).is_some_and(|b| Arc::ptr_eq(a, b)))
+            .count();
+        dense + sparse
+    }
+
+    // Addresses whose values changed going from this memory to the other, in
+    // order. Pages the two still share are skipped without being compared.
+    pub fn diff(&self, other: &Memory) -> Vec<Change> {
+        let dense = 0..self.pages.len().max(other.pages.len());
+        let sparse: BTreeSet<_> = self.sparse.keys().chain(other.sparse.keys()).collect();
+        let mut changes = vec![];
+        for index in dense.chain(sparse.into_iter().copied()) {
+            let (a, b) = (self.page(index), other.page(index));
+            if let (Some(a), Some(b)) = (a, b) {
+                if Arc::ptr_eq(a, b) {
+                    continue;
+                }
+            }
+            for offset in 0..PAGE_SIZE {
+                let old = a.map_or(0, |page| page[offset]);
+                let new = b.map_or(0, |page| page[offset]);
+                if old != new {
+                    let addr = (index << PAGE_BITS) | offset;
+                    changes.push(Change { addr, old, new });
+                }
+            }
+        }
+        changes
+    }
+}
+
+impl From<Vec<i64>> for Memory {
+    fn from(values: Vec<i64>) -> Self {
+        let mut memory = Self::new();
+        for (addr, value) in values.into_iter().enumerate() {
+            memory.set(addr, value);
+        }
+        memory
+    }
+}
+
+impl PartialEq for Memory {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.diff(other).is_empty()
+    }
+}
+
+impl Eq for Memory {}
+
+impl PartialEq<[i64]> for Memory {
+    fn eq(&self, other: &[i64]) -> bool {
+        self.len == other.len()
+            && self.dense_len == self.len
+            && self.iter().eq(other.iter().copied())
+    }
+}
+
+impl<const N: usize> PartialEq<[i64; N]> for Memory {
+    fn eq(&self, other: &[i64; N]) -> bool {
+        *self == other[..]
+    }
+}
+
+// The dense pages as a list, followed by any non-zero values in the sparse
+// pages by address
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.sparse.is_empty() {
+            return f.debug_list().entries(self.iter()).finish();
+        }
+        f.debug_struct("Memory")
+            .field("dense", &self.to_vec())
+            .field("sparse", &Sparse(self))
+            .finish()
+    }
+}
+
+struct Sparse<'a>(&'a Memory);
+
+impl fmt::Debug for Sparse<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let values = self
+            .0
+            .pages()
+            .filter(|(start, _)| start >> PAGE_BITS >= DENSE_PAGES)
+            .flat_map(|(start, page)| (start..).zip(page))
+            .filter(|(_, value)| **value != 0);
+        f.debug_map().entries(values).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_set() {
+        let mut memory = Memory::from(vec![1, 2, 3]);
+        assert_eq!(memory, [1, 2, 3]);
+        assert_eq!(memory.get(1000), 0);
+        memory.set(1000, 7);
+        assert_eq!(memory.len(), 1001);
+        assert_eq!(memory.get(1000), 7);
+        assert_eq!(memory.get(999), 0);
+    }
+
+    #[test]
+    fn test_sparse() {
+        let mut memory = Memory::new();
+        memory.set(1 << 40, 5);
+        assert_eq!(memory.get(1 << 40), 5);
+        assert_eq!(memory.len(), (1 << 40) + 1);
+        assert!(memory.pages.is_empty());
+
+        // Without going through every address up to it
+        memory.set(3, 1);
+        assert_eq!(memory.dense_len(), 4);
+        assert_eq!(memory.to_vec(), [0, 0, 0, 1]);
+        assert_eq!(
+            memory.pages().map(|(start, _)| start).collect::<Vec<_>>(),
+            [0, 1 << 40]
+        );
+        let mut other = memory.clone();
+        assert_eq!(memory, other);
+        other.set(1 << 40, 6);
+        assert_ne!(memory, other);
+        assert_eq!(
+            format!("{:?}", memory),
+            "Memory { dense: [0, 0, 0, 1], sparse: {1099511627776: 5} }"
+        );
+    }
+
+    #[test]
+    fn test_copy_on_write() {
+        let original = Memory::from((0..2000).collect::<Vec<_>>());
+        let mut copy = original.clone();
+        assert_eq!(copy.shared_pages(&original), 4);
+        copy.set(600, -1);
+        assert_eq!(copy.shared_pages(&original), 3);
+        assert_eq!(original.get(600), 600);
+        assert_eq!(copy.get(600), -1);
+    }
+
+    #[test]
+    fn test_diff() {
+        let original = Memory::from((0..2000).collect::<Vec<_>>());
+        let mut copy = original.clone();
+        copy.set(600, -1);
+        copy.set(1 << 40, 5);
+        assert_eq!(
+            original.diff(&copy),
+            [
+                Change {
+                    addr: 600,
+                    old: 600,
+                    new: -1
+                },
+                Change {
+                    addr: 1 << 40,
+                    old: 0,
+                    new: 5
+                }
+            ]
+        );
+        assert!(copy.diff(&copy.clone()).is_empty());
+    }
+}
@@ -0,0 +1,352 @@
+use crate::{channel, run_shared_intcode, Handle, Intcode, Receiver, RunState, Runtime, Sender};
+use std::cell::{Ref, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+// How output from one node reaches the others
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Topology {
+    // Each node's output goes to the input of the next (the last feeding
+    // back into the first)
+    Ring,
+    // Packets are prefixed with the address of the node to deliver to
+    Bus,
+    // A bus with a NAT listening on an extra address. The NAT keeps the last
+    // packet sent to it and, whenever the network goes idle, sends that to
+    // node 0.
+    Star { nat: i64 },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Packet {
+    pub from: i64,
+    pub to: i64,
+    pub payload: Vec<i64>,
+}
+
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}:", self.from, self.to)?;
+        for (i, value) in self.payload.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    Sent(Packet),
+    // The NAT woke an idle network with this packet
+    Nat(Packet),
+}
+
+struct Node {
+    intcode: Rc<RefCell<Intcode>>,
+    input: Sender<i64>,
+    output: Receiver<i64>,
+    // Output not yet making up a whole packet
+    pending: Vec<i64>,
+    task: Handle<RunState>,
+    halted: bool,
+}
+
+// N copies of an Intcode program exchanging packets. Each node is a task on
+// a Runtime, run whenever packets are delivered to it; the network is idle
+// once no node can run.
+pub struct Network {
+    runtime: Runtime,
+    nodes: Vec<Node>,
+    topology: Topology,
+    payload: usize,
+    idle_input: Option<i64>,
+    // Whether a packet was sent since idle input was last given
+    busy: bool,
+    nat: Option<Packet>,
+    log: Vec<Packet>,
+}
+
+impl Network {
+    pub fn new(intcode: &Intcode, size: usize, topology: Topology) -> Self {
+        let mut runtime = Runtime::new();
+        let nodes = (0..size)
+            .map(|_| {
+                let intcode = Rc::new(RefCell::new(intcode.clone()));
+                let (input, rx) = channel();
+                let (tx, output) = channel();
+                let task = runtime.spawn(run_shared_intcode(intcode.clone(), rx, tx));
+                Node {
+                    intcode,
+                    input,
+                    output,
+                    pending: vec![],
+                    task,
+                    halted: false,
+                }
+            })
+            .collect();
+        let payload = match topology {
+            Topology::Ring => 1,
+            Topology::Bus | Topology::Star { .. } => 2,
+        };
+        Self {
+            runtime,
+            nodes,
+            topology,
+            payload,
+            idle_input: None,
+            busy: true,
+            nat: None,
+            log: vec![],
+        }
+    }
+
+    // Number of values in a packet (not counting the address)
+    pub fn framing(&mut self, payload: usize) {
+        self.payload = payload;
+    }
+
+    // Value given to every node waiting for input when the network would
+    // otherwise be idle (rather than leaving them waiting). The network is
+    // only idle once none of them send anything in response.
+    pub fn idle_input(&mut self, value: Option<i64>) {
+        self.idle_input = value;
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn node(&self, addr: usize) -> Ref<'_, Intcode> {
+        self.nodes[addr].intcode.borrow()
+    }
+
+    // Queues raw input for a node (e.g. to boot it)
+    pub fn send(&mut self, addr: usize, values: &[i64]) {
+        for value in values {
+            self.nodes[addr].input.send(*value);
+        }
+    }
+
+    // Every packet sent so far, in order
+    pub fn log(&self) -> &[Packet] {
+        &self.log
+    }
+
+    pub fn nat(&self) -> Option<&Packet> {
+        self.nat.as_ref()
+    }
+
+    // Runs until stop returns true for an event (which is returned), or
+    // until every node halts or is stuck waiting for input
+    pub fn run<F>(&mut self, mut stop: F) -> Option<Event>
+    where
+        F: FnMut(&Event) -> bool,
+    {
+        loop {
+            // Node tasks were spawned first, so their ids are their addresses
+            if let Some(addr) = self.runtime.step() {
+                while let Some(packet) = self.next_packet(addr) {
+                    self.busy = true;
+                    self.deliver(&packet);
+                    let event = Event::Sent(packet);
+                    if stop(&event) {
+                        return Some(event);
+                    }
+                }
+                continue;
+            }
+
+            // Nothing can run, so every node has halted or is waiting for
+            // input (any left queued for a halted node will never be read)
+            for node in &mut self.nodes {
+                node.halted |= node.task.take().is_some();
+            }
+            if self.nodes.iter().all(|node| node.halted) {
+                return None;
+            }
+            if let Some(value) = self.idle_input.filter(|_| self.busy) {
+                self.busy = false;
+                for node in self.nodes.iter().filter(|node| !node.halted) {
+                    node.input.send(value);
+                }
+                continue;
+            }
+            let packet = self.wake()?;
+            self.busy = true;
+            self.deliver(&packet);
+            let event = Event::Nat(packet);
+            if stop(&event) {
+                return Some(event);
+            }
+        }
+    }
+
+    // The next whole packet a node has output, if any
+    fn next_packet(&mut self, addr: usize) -> Option<Packet> {
+        let frame = match self.topology {
+            Topology::Ring => self.payload,
+            Topology::Bus | Topology::Star { .. } => self.payload + 1,
+        };
+        let len = self.nodes.len();
+        let node = &mut self.nodes[addr];
+        node.pending.extend(node.output.drain());
+        if node.pending.len() < frame {
+            return None;
+        }
+        let frame: Vec<_> = node.pending.drain(..frame).collect();
+        let from = addr as i64;
+        Some(match self.topology {
+            Topology::Ring => Packet {
+                from,
+                to: ((addr + 1) % len) as i64,
+                payload: frame,
+            },
+            Topology::Bus | Topology::Star { .. } => Packet {
+                from,
+                to: frame[0],
+                payload: frame[1..].to_vec(),
+            },
+        })
+    }
+
+    // Packets to unknown addresses are logged but otherwise dropped
+    fn deliver(&mut self, packet: &Packet) {
+        self.log.push(packet.clone());
+        match self.topology {
+            Topology::Star { nat } if packet.to == nat => {
+                self.nat = Some(packet.clone());
+            }
+            _ => {
+                if let Some(node) = usize::try_from(packet.to)
+                    .ok()
+                    .and_then(|to| self.nodes.get(to))
+                {
+                    for value in &packet.payload {
+                        node.input.send(*value);
+                    }
+                }
+            }
+        }
+    }
+
+    // The packet to restart an idle network with (if it can be)
+    fn wake(&self) -> Option<Packet> {
+        match self.topology {
+            Topology::Star { nat } => self.nat.as_ref().map(|packet| Packet {
+                from: nat,
+                to: 0,
+                payload: packet.payload.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring() {
+        // Keeps a running total of its inputs, outputting it after each one
+        let adder = Intcode::new("3,14,3,15,1,14,15,14,4,14,1105,1,2,0,0,0");
+        let mut network = Network::new(&adder, 3, Topology::Ring);
+        for addr in 0..3 {
+            network.send(addr, &[addr as i64 + 1]);
+        }
+        network.send(0, &[0]);
+        let event = network.run(|event| matches!(event, Event::Sent(p) if p.payload[0] > 20));
+        assert_eq!(
+            event,
+            Some(Event::Sent(Packet {
+                from: 0,
+                to: 1,
+                payload: vec![23]
+            }))
+        );
+        let sums: Vec<_> = network.log().iter().map(|p| p.payload[0]).collect();
+        assert_eq!(sums, [1, 3, 6, 7, 10, 16, 23]);
+    }
+
+    #[test]
+    fn test_star() {
+        // Sends (x, y) to the NAT at 255, then sends on whatever it receives
+        // with y incremented to the node at x (or the NAT when x = 0)
+        let node = crate::assemble(
+            "
+                    in -> [addr]
+                    jt [addr], #loop
+                    out #255
+                    out #0
+                    out #0
+            loop:   in -> [x]
+                    in -> [y]
+                    add [y], #1 -> [y]
+                    eq [x], #0 -> [t]
+                    jf [t], #send
+                    add #255, #0 -> [x]
+            send:   out [x]
+                    out [addr]
+                    out [y]
+                    jt #1, #loop
+            addr:   db 0
+            x:      db 0
+            y:      db 0
+            t:      db 0
+            ",
+        )
+        .unwrap();
+        let node = Intcode::new(&node);
+        let mut network = Network::new(&node, 2, Topology::Star { nat: 255 });
+        network.send(0, &[0]);
+        network.send(1, &[1]);
+        let event = network.run(|event| matches!(event, Event::Nat(p) if p.payload[1] >= 3));
+        assert_eq!(
+            event,
+            Some(Event::Nat(Packet {
+                from: 255,
+                to: 0,
+                payload: vec![0, 3]
+            }))
+        );
+        assert_eq!(
+            network
+                .log()
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "0 -> 255: 0, 0",
+                "255 -> 0: 0, 0",
+                "0 -> 255: 0, 1",
+                "255 -> 0: 0, 1",
+                "0 -> 255: 0, 2",
+                "255 -> 0: 0, 2",
+                "0 -> 255: 0, 3",
+                "255 -> 0: 0, 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_halted() {
+        let mut network = Network::new(&Intcode::new("3,5,4,5,99,0"), 2, Topology::Ring);
+        network.send(0, &[7]);
+        assert_eq!(network.run(|_| false), None);
+        assert_eq!(network.log().len(), 2);
+        assert!(network.node(0).is_halted());
+    }
+
+    #[test]
+    fn test_halted_with_input() {
+        // Outputs its inputs until one is 0, when it halts
+        let echo = Intcode::new("3,11,1006,11,10,4,11,1105,1,0,99,0");
+        let mut network = Network::new(&echo, 2, Topology::Ring);
+        network.send(1, &[0, 5]);
+        assert_eq!(network.run(|_| false), None);
+        assert!(network.node(1).is_halted());
+        assert!(!network.node(0).is_halted());
+    }
+}
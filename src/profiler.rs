@@ -0,0 +1,164 @@
+use crate::{Instruction, TraceEvent, Tracer};
+use hashbrown::HashMap;
+use itertools::Itertools;
+use std::fmt;
+use std::io::Write;
+
+// Writes each executed instruction as a line to the sink
+pub struct TraceWriter<W: Write>(pub W);
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        writeln!(self.0, "{}", event).expect("Failure writing trace");
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    cycles: u64,
+    by_instruction: HashMap<Instruction, u64>,
+    // Extension instructions, by opcode
+    by_extension: HashMap<i64, u64>,
+    by_addr: HashMap<usize, u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn count(&self, instruction: Instruction) -> u64 {
+        self.by_instruction.get(&instruction).copied().unwrap_or(0)
+    }
+
+    pub fn count_extension(&self, opcode: i64) -> u64 {
+        self.by_extension.get(&opcode).copied().unwrap_or(0)
+    }
+
+    pub fn count_at(&self, addr: usize) -> u64 {
+        self.by_addr.get(&addr).copied().unwrap_or(0)
+    }
+
+    // Most executed instructions first
+    pub fn instructions(&self) -> Vec<(Instruction, u64)> {
+        let mut counts: Vec<_> = self.by_instruction.iter().map(|(i, n)| (*i, *n)).collect();
+        counts.sort_by_key(|(i, n)| (std::cmp::Reverse(*n), i.opcode()));
+        counts
+    }
+
+    // The n most executed addresses, hottest first
+    pub fn hottest(&self, n: usize) -> Vec<(usize, u64)> {
+        let mut counts: Vec<_> = self.by_addr.iter().map(|(a, n)| (*a, *n)).collect();
+        counts.sort_by_key(|(a, n)| (std::cmp::Reverse(*n), *a));
+        counts.truncate(n);
+        counts
+    }
+}
+
+impl Tracer for Profiler {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.cycles += 1;
+        match event.instruction {
+            Some(instruction) => *self.by_instruction.entry(instruction).or_default() += 1,
+            None => *self.by_extension.entry(event.opcode).or_default() += 1,
+        }
+        *self.by_addr.entry(event.addr).or_default() += 1;
+    }
+}
+
+impl fmt::Display for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |n: u64| 100.0 * n as f64 / self.cycles.max(1) as f64;
+        writeln!(f, "Cycles: {}", self.cycles)?;
+        writeln!(f, "By instruction:")?;
+        for (instruction, n) in self.instructions() {
+            writeln!(
+                f,
+                "  {:<4} {:>10} {:>6.1}%",
+                instruction.mnemonic(),
+                n,
+                percent(n)
+            )?;
+        }
+        for (opcode, n) in self.by_extension.iter().sorted() {
+            writeln!(f, "  op{:<2} {:>10} {:>6.1}%", opcode, n, percent(*n))?;
+        }
+        writeln!(f, "Hottest addresses:")?;
+        for (addr, n) in self.hottest(10) {
+            writeln!(f, "  {:04} {:>10} {:>6.1}%", addr, n, percent(n))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Arithmetic, Effect, Extension, InstructionSet, Intcode};
+
+    #[test]
+    fn test_profile() {
+        let mut intcode = Intcode::new("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99");
+        let mut profiler = Profiler::new();
+        intcode.run_traced(|| None, |_| {}, &mut profiler);
+        assert_eq!(profiler.cycles(), 16 * 5 + 1);
+        assert_eq!(profiler.count(Instruction::Output), 16);
+        assert_eq!(profiler.count(Instruction::Halt), 1);
+        assert_eq!(profiler.count_at(4), 16);
+        assert_eq!(profiler.hottest(1), [(0, 16)]);
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut intcode = Intcode::new("3,9,1001,9,5,9,4,9,99,0");
+        let mut events = vec![];
+        let mut sink = vec![];
+        intcode.run_traced(
+            || Some(7),
+            |_| {},
+            &mut (&mut events, TraceWriter(&mut sink)),
+        );
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[1].operands(), [7, 5]);
+        assert_eq!(events[1].write, Some((9, 12)));
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "0000: in -> [9] = 7
+0002: add 7, 5 -> [9] = 12
+0006: out 12
+0008: hlt
+"
+        );
+    }
+
+    #[test]
+    fn test_profile_big_and_extensions() {
+        // Squares [11] (2^40) twice, then outputs whether it's nonzero
+        let code = "2,11,11,11,2,11,11,11,1005,11,13,3";
+        let mut intcode = Intcode::new(&format!("{},1099511627776,104,1,99", code));
+        intcode.set_arithmetic(Arithmetic::Big);
+        let mut profiler = Profiler::new();
+        let mut output = vec![];
+        intcode.run_traced(|| None, |v| output.push(v), &mut profiler);
+        assert_eq!(output, [1]);
+        assert_eq!(profiler.cycles(), 5);
+        assert_eq!(profiler.count(Instruction::Multiply), 2);
+        assert_eq!(profiler.count(Instruction::JumpIfTrue), 1);
+
+        // dbl #4 -> [4], twice
+        let isa = InstructionSet::full().with(Extension::new(42, "dbl", 2, true, |values| {
+            Effect::Write(values[0] * 2)
+        }));
+        let mut intcode = Intcode::new("142,4,4,42,4,4,99");
+        intcode.set_instruction_set(isa);
+        let mut profiler = Profiler::new();
+        intcode.run_traced(|| None, |_| {}, &mut profiler);
+        assert_eq!(profiler.cycles(), 3);
+        assert_eq!(profiler.count_extension(42), 2);
+        assert!(profiler.to_string().contains("  op42          2   66.7%"));
+    }
+}
@@ -0,0 +1,424 @@
+use crate::{Intcode, IntcodeError, IntcodeIO, RunState};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+// A value passing between a machine and its driver, with the number of
+// instructions the machine had executed (since recording started) when it
+// happened
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IoEvent {
+    Input { cycle: u64, value: i64 },
+    Output { cycle: u64, value: i64 },
+}
+
+impl fmt::Display for IoEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IoEvent::Input { cycle, value } => write!(f, "in {} {}", cycle, value),
+            IoEvent::Output { cycle, value } => write!(f, "out {} {}", cycle, value),
+        }
+    }
+}
+
+// Every I/O event of a run, stored as text with one event per line:
+//
+//   intcode-session 1
+//   in 2 5
+//   out 9 10
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Session {
+    pub events: Vec<IoEvent>,
+}
+
+// Where a replay first differed from the recording. Either event is None
+// when that side had already finished, or the machine faulted instead.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Divergence {
+    pub index: usize,
+    pub expected: Option<IoEvent>,
+    pub actual: Option<IoEvent>,
+    pub fault: Option<IntcodeError>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let event = |event: Option<IoEvent>| event.map_or("end".to_string(), |e| e.to_string());
+        write!(
+            f,
+            "Divergence at event {}: expected {}, got ",
+            self.index,
+            event(self.expected)
+        )?;
+        match &self.fault {
+            Some(fault) => write!(f, "fault ({})", fault),
+            None => write!(f, "{}", event(self.actual)),
+        }
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(std::io::Error),
+    Format { line: usize, message: String },
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::Io(err) => write!(f, "{}", err),
+            RecordingError::Format { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {}
+
+impl From<std::io::Error> for RecordingError {
+    fn from(err: std::io::Error) -> Self {
+        RecordingError::Io(err)
+    }
+}
+
+impl Session {
+    const HEADER: &'static str = "intcode-session 1";
+
+    pub fn inputs(&self) -> impl Iterator<Item = i64> + '_ {
+        self.events.iter().filter_map(|event| match event {
+            IoEvent::Input { value, .. } => Some(*value),
+            IoEvent::Output { .. } => None,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RecordingError> {
+        Ok(fs::write(path, self.to_string())?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    // Feeds the recorded inputs back into the machine, checking that it asks
+    // for and produces the same values at the same cycles. Runs until the
+    // machine halts or wants more input than was recorded.
+    pub fn replay(&self, intcode: &mut Intcode) -> Result<RunState, Divergence> {
+        let cycles = Cell::new(0);
+        let next = Cell::new(0);
+        let divergence = RefCell::new(None);
+        let check = |actual: IoEvent| {
+            let index = next.get();
+            let expected = self.events.get(index).copied();
+            let mut divergence = divergence.borrow_mut();
+            if expected != Some(actual) && divergence.is_none() {
+                *divergence = Some(Divergence {
+                    index,
+                    expected,
+                    actual: Some(actual),
+                    fault: None,
+                });
+            }
+            next.set(index + 1);
+        };
+
+        let mut inputs = self.inputs();
+        let mut state;
+        loop {
+            let result = run_counted(
+                intcode,
+                &cycles,
+                || {
+                    // Stop at the first divergence, or when out of input
+                    if divergence.borrow().is_some() {
+                        return None;
+                    }
+                    let value = inputs.next()?;
+                    check(IoEvent::Input {
+                        cycle: cycles.get(),
+                        value,
+                    });
+                    Some(value)
+                },
+                |value| {
+                    check(IoEvent::Output {
+                        cycle: cycles.get(),
+                        value,
+                    })
+                },
+            );
+            state = match result {
+                Ok(state) => state,
+                Err(fault) => {
+                    let index = next.get();
+                    return Err(divergence.into_inner().unwrap_or(Divergence {
+                        index,
+                        expected: self.events.get(index).copied(),
+                        actual: None,
+                        fault: Some(fault),
+                    }));
+                }
+            };
+            if divergence.borrow().is_some() || !matches!(state, RunState::Output(_)) {
+                break;
+            }
+        }
+
+        match divergence.into_inner() {
+            Some(divergence) => Err(divergence),
+            None if next.get() < self.events.len() => Err(Divergence {
+                index: next.get(),
+                expected: Some(self.events[next.get()]),
+                actual: None,
+                fault: None,
+            }),
+            None => Ok(state),
+        }
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", Self::HEADER)?;
+        for event in &self.events {
+            writeln!(f, "{}", event)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Session {
+    type Err = RecordingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |line: usize, message: String| RecordingError::Format { line, message };
+        let mut lines = s.lines().enumerate().map(|(i, line)| (i + 1, line.trim()));
+
+        match lines.next() {
+            Some((_, Self::HEADER)) => {}
+            _ => return Err(err(1, format!("expected {:?}", Self::HEADER))),
+        }
+
+        let mut events = vec![];
+        for (line, text) in lines.filter(|(_, text)| !text.is_empty()) {
+            let parts: Vec<_> = text.split_whitespace().collect();
+            let [kind, cycle, value] = parts[..] else {
+                return Err(err(line, format!("invalid event {:?}", text)));
+            };
+            let cycle = cycle
+                .parse()
+                .map_err(|_| err(line, format!("invalid cycle {:?}", cycle)))?;
+            let value = value
+                .parse()
+                .map_err(|_| err(line, format!("invalid value {:?}", value)))?;
+            events.push(match kind {
+                "in" => IoEvent::Input { cycle, value },
+                "out" => IoEvent::Output { cycle, value },
+                _ => return Err(err(line, format!("invalid event {:?}", text))),
+            });
+        }
+        Ok(Self { events })
+    }
+}
+
+// Runs the machine an instruction at a time, counting those completed
+fn run_counted<I, O>(
+    intcode: &mut Intcode,
+    cycles: &Cell<u64>,
+    mut input: I,
+    mut output: O,
+) -> Result<RunState, IntcodeError>
+where
+    I: FnMut() -> Option<i64>,
+    O: FnMut(i64),
+{
+    loop {
+        let state = intcode.try_run_for(&mut input, &mut output, 1)?;
+        if matches!(state, RunState::StepLimit | RunState::Output(_)) {
+            cycles.set(cycles.get() + 1);
+        }
+        if state != RunState::StepLimit {
+            return Ok(state);
+        }
+    }
+}
+
+// Wraps a driver, recording everything passing between it and the machine
+pub struct Recorder<IO: IntcodeIO> {
+    io: IO,
+    cycles: u64,
+    session: Session,
+}
+
+impl<IO: IntcodeIO> Recorder<IO> {
+    pub fn new(io: IO) -> Self {
+        Self {
+            io,
+            cycles: 0,
+            session: Session::default(),
+        }
+    }
+
+    pub fn io(&mut self) -> &mut IO {
+        &mut self.io
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn into_inner(self) -> (IO, Session) {
+        (self.io, self.session)
+    }
+
+    // Like Intcode::run_with_io. Can be called repeatedly (e.g. after the
+    // machine needs input), with cycles counted across calls.
+    pub fn run(&mut self, intcode: &mut Intcode) -> RunState {
+        let cycles = Cell::new(self.cycles);
+        let io = RefCell::new(&mut self.io);
+        let events = RefCell::new(&mut self.session.events);
+        let result = run_counted(
+            intcode,
+            &cycles,
+            || {
+                let value = io.borrow_mut().input()?;
+                events.borrow_mut().push(IoEvent::Input {
+                    cycle: cycles.get(),
+                    value,
+                });
+                Some(value)
+            },
+            |value| {
+                events.borrow_mut().push(IoEvent::Output {
+                    cycle: cycles.get(),
+                    value,
+                });
+                io.borrow_mut().output(value);
+            },
+        );
+        self.cycles = cycles.get();
+        match result {
+            Ok(state) => state,
+            Err(err) => panic!("{}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Outputs double each input until it sees a zero
+    const DOUBLER: &str = "3,15,1006,15,14,1002,15,2,15,4,15,1105,1,0,99,0";
+
+    struct Countdown(i64);
+
+    impl IntcodeIO for Countdown {
+        fn input(&mut self) -> Option<i64> {
+            self.0 -= 1;
+            Some(self.0)
+        }
+
+        fn output(&mut self, _value: i64) {}
+    }
+
+    fn record() -> Session {
+        let mut recorder = Recorder::new(Countdown(3));
+        assert_eq!(recorder.run(&mut Intcode::new(DOUBLER)), RunState::Halted);
+        recorder.into_inner().1
+    }
+
+    #[test]
+    fn test_record() {
+        let session = record();
+        assert_eq!(
+            session.to_string(),
+            "intcode-session 1
+in 0 2
+out 3 4
+in 5 1
+out 8 2
+in 10 0
+"
+        );
+        assert_eq!(session.to_string().parse::<Session>().unwrap(), session);
+        assert_eq!(session.inputs().collect::<Vec<_>>(), [2, 1, 0]);
+
+        let err = "intcode-session 1\nin 0 2\nout x 4\n"
+            .parse::<Session>()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "line 3: invalid cycle \"x\"");
+    }
+
+    #[test]
+    fn test_replay() {
+        let session = record();
+        assert_eq!(
+            session.replay(&mut Intcode::new(DOUBLER)),
+            Ok(RunState::Halted)
+        );
+
+        // Tripling instead
+        let tripler = DOUBLER.replace(",2,15,", ",3,15,");
+        assert_eq!(
+            session.replay(&mut Intcode::new(&tripler)),
+            Err(Divergence {
+                index: 1,
+                expected: Some(IoEvent::Output { cycle: 3, value: 4 }),
+                actual: Some(IoEvent::Output { cycle: 3, value: 6 }),
+                fault: None,
+            })
+        );
+
+        // Expecting more than happens
+        let mut extended = session.clone();
+        extended.events.push(IoEvent::Output {
+            cycle: 11,
+            value: 0,
+        });
+        let err = extended.replay(&mut Intcode::new(DOUBLER)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Divergence at event 5: expected out 11 0, got end"
+        );
+    }
+
+    #[test]
+    fn test_replay_fault() {
+        let session = record();
+        // A bad opcode where the doubling was
+        let faulty = DOUBLER.replacen("1002", "98", 1);
+        let err = session.replay(&mut Intcode::new(&faulty)).unwrap_err();
+        assert_eq!(
+            err,
+            Divergence {
+                index: 1,
+                expected: Some(IoEvent::Output { cycle: 3, value: 4 }),
+                actual: None,
+                fault: Some(IntcodeError::BadOpcode {
+                    value: 98,
+                    ptr: 5,
+                    relbase: 0,
+                }),
+            }
+        );
+        assert!(err
+            .to_string()
+            .starts_with("Divergence at event 1: expected out 3 4, got fault ("));
+    }
+
+    #[test]
+    fn test_untraced_steps() {
+        // Compares a value too big for an i64 then outputs the result, so the
+        // first instruction runs at full precision
+        let mut intcode = Intcode::new("7,7,8,9,4,9,99,0,0,5");
+        intcode.set_big(7, num::BigInt::from(1) << 70);
+        let mut recorder = Recorder::new(Countdown(0));
+        assert_eq!(recorder.run(&mut intcode.clone()), RunState::Halted);
+        let session = recorder.into_inner().1;
+        assert_eq!(session.events, [IoEvent::Output { cycle: 1, value: 0 }]);
+        assert_eq!(session.replay(&mut intcode), Ok(RunState::Halted));
+    }
+}
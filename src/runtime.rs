@@ -0,0 +1,301 @@
+use crate::{Intcode, RunState};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+// A single threaded executor for cooperatively running many Intcode machines
+// (or any other futures) connected by channels. Tasks only run when woken by
+// a channel they are waiting on, so when nothing is left to run but some
+// tasks haven't finished, those tasks are deadlocked.
+#[derive(Default)]
+pub struct Runtime {
+    tasks: Vec<Option<Pin<Box<dyn Future<Output = ()>>>>>,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+// Tasks still waiting when nothing else could run
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Deadlock {
+    pub blocked: Vec<usize>,
+}
+
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Deadlock: tasks {:?} are blocked", self.blocked)
+    }
+}
+
+impl std::error::Error for Deadlock {}
+
+struct TaskWaker {
+    id: usize,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.ready.lock().unwrap().push_back(self.id);
+    }
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Spawns a future, returning a handle to collect its result once run
+    pub fn spawn<T: 'static>(&mut self, future: impl Future<Output = T> + 'static) -> Handle<T> {
+        let handle = Handle {
+            id: self.tasks.len(),
+            result: Rc::new(RefCell::new(None)),
+        };
+        let result = handle.result.clone();
+        self.tasks.push(Some(Box::pin(async move {
+            *result.borrow_mut() = Some(future.await);
+        })));
+        self.ready.lock().unwrap().push_back(handle.id);
+        handle
+    }
+
+    // Runs a machine reading from input and writing to output until it
+    // halts, or needs input after every sender for input has been dropped.
+    // Dropping output when done lets whatever is downstream finish too.
+    pub fn spawn_intcode(
+        &mut self,
+        intcode: Intcode,
+        input: Receiver<i64>,
+        output: Sender<i64>,
+    ) -> Handle<Intcode> {
+        self.spawn(run_intcode(intcode, input, output))
+    }
+
+    // Runs until every task has finished, or no task can make progress
+    pub fn run(&mut self) -> Result<(), Deadlock> {
+        while self.step().is_some() {}
+
+        let blocked: Vec<_> = (0..self.tasks.len())
+            .filter(|id| self.tasks[*id].is_some())
+            .collect();
+        if blocked.is_empty() {
+            Ok(())
+        } else {
+            Err(Deadlock { blocked })
+        }
+    }
+
+    // Polls the next task ready to run, returning its id, or None if every
+    // task has finished or is waiting
+    pub fn step(&mut self) -> Option<usize> {
+        loop {
+            let id = self.ready.lock().unwrap().pop_front()?;
+            let Some(task) = self.tasks[id].as_mut() else {
+                continue; // Woken after finishing
+            };
+            let waker = Waker::from(Arc::new(TaskWaker {
+                id,
+                ready: self.ready.clone(),
+            }));
+            if task
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_ready()
+            {
+                self.tasks[id] = None;
+            }
+            return Some(id);
+        }
+    }
+}
+
+pub struct Handle<T> {
+    id: usize,
+    result: Rc<RefCell<Option<T>>>,
+}
+
+impl<T> Handle<T> {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    // The task's result, if it has finished (and not already been taken)
+    pub fn take(&self) -> Option<T> {
+        self.result.borrow_mut().take()
+    }
+}
+
+struct Channel<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    waker: Option<Waker>,
+}
+
+impl<T> Channel<T> {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+// Unbounded channel for use between tasks of the same runtime
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let channel = Rc::new(RefCell::new(Channel {
+        queue: VecDeque::new(),
+        senders: 1,
+        waker: None,
+    }));
+    (Sender(channel.clone()), Receiver(channel))
+}
+
+pub struct Sender<T>(Rc<RefCell<Channel<T>>>);
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) {
+        let mut channel = self.0.borrow_mut();
+        channel.queue.push_back(value);
+        channel.wake();
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.0.borrow_mut().senders += 1;
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut channel = self.0.borrow_mut();
+        channel.senders -= 1;
+        if channel.senders == 0 {
+            channel.wake();
+        }
+    }
+}
+
+pub struct Receiver<T>(Rc<RefCell<Channel<T>>>);
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&self) -> Option<T> {
+        self.0.borrow_mut().queue.pop_front()
+    }
+
+    // Waits for the next value, or None once every sender is gone
+    pub fn recv(&self) -> Recv<'_, T> {
+        Recv(self)
+    }
+
+    // Everything sent but not yet received
+    pub fn drain(&self) -> Vec<T> {
+        self.0.borrow_mut().queue.drain(..).collect()
+    }
+}
+
+pub struct Recv<'a, T>(&'a Receiver<T>);
+
+impl<T> Future for Recv<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut channel = self.0 .0.borrow_mut();
+        if let Some(value) = channel.queue.pop_front() {
+            Poll::Ready(Some(value))
+        } else if channel.senders == 0 {
+            Poll::Ready(None)
+        } else {
+            channel.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+pub async fn run_intcode(intcode: Intcode, input: Receiver<i64>, output: Sender<i64>) -> Intcode {
+    let intcode = Rc::new(RefCell::new(intcode));
+    run_shared_intcode(intcode.clone(), input, output).await;
+    Rc::into_inner(intcode).unwrap().into_inner()
+}
+
+// As run_intcode, for a machine that can be looked at while the task waits
+// (it's only borrowed while running), returning why it stopped
+pub async fn run_shared_intcode(
+    intcode: Rc<RefCell<Intcode>>,
+    input: Receiver<i64>,
+    output: Sender<i64>,
+) -> RunState {
+    let mut next = None;
+    loop {
+        let state = intcode.borrow_mut().run(
+            || next.take().or_else(|| input.try_recv()),
+            |v| output.send(v),
+        );
+        match state {
+            RunState::NeedsInput => match input.recv().await {
+                Some(value) => next = Some(value),
+                None => return state,
+            },
+            RunState::Output(_) => {}
+            RunState::Halted
+            | RunState::StepLimit
+            | RunState::OutOfFuel
+            | RunState::OutOfMemory(_)
+            | RunState::Exit(_) => return state,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Adds one to each input until it sees a zero
+    const INC: &str = "3,15,1006,15,14,1001,15,1,15,4,15,1105,1,0,99,0";
+
+    #[test]
+    fn test_pipeline() {
+        let mut runtime = Runtime::new();
+        let (tx, mut rx) = channel();
+        let first = tx.clone();
+        for _ in 0..3 {
+            let (next_tx, next_rx) = channel();
+            runtime.spawn_intcode(Intcode::new(INC), rx, next_tx);
+            rx = next_rx;
+        }
+        first.send(5);
+        drop((tx, first));
+        assert_eq!(runtime.run(), Ok(()));
+        assert_eq!(rx.drain(), [8]);
+    }
+
+    #[test]
+    fn test_feedback_loop() {
+        // Counts down from the input, feeding its output back to itself
+        let countdown = "3,13,1001,13,-1,13,4,13,1005,13,0,99,0,0";
+        let mut runtime = Runtime::new();
+        let (tx, rx) = channel();
+        tx.send(3);
+        let handle = runtime.spawn_intcode(Intcode::new(countdown), rx, tx);
+        assert_eq!(runtime.run(), Ok(()));
+        assert!(handle.take().unwrap().is_halted());
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let mut runtime = Runtime::new();
+        let (tx, rx) = channel();
+        let (tx2, rx2) = channel();
+        let a = runtime.spawn_intcode(Intcode::new(INC), rx, tx2);
+        let b = runtime.spawn_intcode(Intcode::new(INC), rx2, tx);
+        assert_eq!(
+            runtime.run(),
+            Err(Deadlock {
+                blocked: vec![a.id(), b.id()]
+            })
+        );
+    }
+}
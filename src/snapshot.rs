@@ -0,0 +1,470 @@
+use crate::{Arithmetic, Instruction, InstructionSet, Intcode, Memory, Mode};
+use itertools::Itertools;
+use num::BigInt;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+// Full machine state plus any I/O not yet consumed / collected, stored as
+// compact text:
+//
+//   intcode-snapshot 2
+//   ptr 12
+//   relbase 5
+//   halt_after_output 0
+//   input 1,2,3
+//   output
+//   memory 109,1,204,0*20,5
+//   arithmetic big
+//   fuel 1000
+//   max_memory
+//   instruction_set add,mul,hlt/0,1
+//   big 20=36893488147419103232
+//
+// Runs of zeros in memory (including the gaps between the pages written)
+// are written as `0*N`. Values too large for an i64 (with big arithmetic)
+// are written as 0 in memory and listed by address in `big`. Limits and the
+// instruction set are left empty when there are none. Version 1 snapshots
+// (which stop after memory) can still be loaded.
+#[derive(Clone, Default)]
+pub struct Snapshot {
+    pub intcode: Intcode,
+    pub input: Vec<i64>,
+    pub output: Vec<i64>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    Format { line: usize, message: String },
+    // Machine state that can't be written out (e.g. extension opcodes)
+    Unsupported(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "{}", err),
+            SnapshotError::Format { line, message } => write!(f, "line {}: {}", line, message),
+            SnapshotError::Unsupported(message) => write!(f, "can't save {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(err: std::io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+impl Snapshot {
+    const HEADER: &'static str = "intcode-snapshot 2";
+    const HEADER_V1: &'static str = "intcode-snapshot 1";
+
+    pub fn new(intcode: &Intcode) -> Self {
+        Self {
+            intcode: intcode.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_io(intcode: &Intcode, input: &[i64], output: &[i64]) -> Self {
+        Self {
+            intcode: intcode.clone(),
+            input: input.to_vec(),
+            output: output.to_vec(),
+        }
+    }
+
+    // Fails for machines with extension opcodes, whose handlers are code
+    // (which to_string leaves out)
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let isa = self.intcode.instruction_set();
+        if let Some(extension) = isa.and_then(|isa| isa.extensions().next()) {
+            let message = format!("extension opcode {:?}", extension);
+            return Err(SnapshotError::Unsupported(message));
+        }
+        Ok(fs::write(path, self.to_string())?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        fs::read_to_string(path)?.parse()
+    }
+}
+
+fn arithmetic_name(arithmetic: Arithmetic) -> &'static str {
+    match arithmetic {
+        Arithmetic::Checked => "checked",
+        Arithmetic::Wrapping => "wrapping",
+        Arithmetic::Big => "big",
+    }
+}
+
+// Memory as (count, value) runs, going a page at a time so that a write to
+// a huge address is just one long run of zeros
+fn memory_runs(memory: &Memory) -> Vec<(usize, i64)> {
+    let mut runs: Vec<(usize, i64)> = vec![];
+    let mut push = |n: usize, v: i64| match runs.last_mut() {
+        Some((count, value)) if *value == v => *count += n,
+        _ if n > 0 => runs.push((n, v)),
+        _ => {}
+    };
+    let mut addr = 0;
+    for (start, page) in memory.pages() {
+        let end = (start + page.len()).min(memory.len());
+        push(start - addr, 0);
+        for &v in &page[..end - start] {
+            push(1, v);
+        }
+        addr = end;
+    }
+    push(memory.len() - addr, 0);
+    runs
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let intcode = &self.intcode;
+        let memory = memory_runs(intcode.memory())
+            .into_iter()
+            .flat_map(|(n, v)| {
+                if v == 0 && n > 2 {
+                    vec![format!("0*{}", n)]
+                } else {
+                    vec![v.to_string(); n]
+                }
+            })
+            .join(",");
+        let fields = [
+            ("ptr", intcode.ptr().to_string()),
+            ("relbase", intcode.relbase().to_string()),
+            (
+                "halt_after_output",
+                (intcode.halts_after_output() as u8).to_string(),
+            ),
+            ("input", self.input.iter().join(",")),
+            ("output", self.output.iter().join(",")),
+            ("memory", memory),
+            (
+                "arithmetic",
+                arithmetic_name(intcode.arithmetic()).to_string(),
+            ),
+            (
+                "fuel",
+                intcode.fuel().map_or(String::new(), |n| n.to_string()),
+            ),
+            (
+                "max_memory",
+                intcode
+                    .max_memory()
+                    .map_or(String::new(), |n| n.to_string()),
+            ),
+            (
+                "instruction_set",
+                intcode.instruction_set().map_or(String::new(), |isa| {
+                    let instructions = isa.instructions().iter().map(|i| i.mnemonic()).join(",");
+                    format!(
+                        "{}/{}",
+                        instructions,
+                        isa.modes().iter().map(Mode::digit).join(",")
+                    )
+                }),
+            ),
+            (
+                "big",
+                intcode
+                    .big_values()
+                    .sorted_by_key(|(addr, _)| *addr)
+                    .map(|(addr, value)| format!("{}={}", addr, value))
+                    .join(","),
+            ),
+        ];
+
+        writeln!(f, "{}", Self::HEADER)?;
+        for (name, value) in fields {
+            if value.is_empty() {
+                writeln!(f, "{}", name)?;
+            } else {
+                writeln!(f, "{} {}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = SnapshotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().enumerate().map(|(i, line)| (i + 1, line.trim()));
+        let err = |line: usize, message: String| SnapshotError::Format { line, message };
+
+        let version = match lines.next() {
+            Some((_, Self::HEADER)) => 2,
+            Some((_, Self::HEADER_V1)) => 1,
+            _ => return Err(err(1, format!("expected {:?}", Self::HEADER))),
+        };
+
+        let last = s.lines().count();
+        let mut field = |name: &str| {
+            let (line, text) = lines
+                .next()
+                .ok_or_else(|| err(last, format!("missing {}", name)))?;
+            let value = text
+                .strip_prefix(name)
+                .filter(|v| v.is_empty() || v.starts_with(' '))
+                .ok_or_else(|| err(line, format!("expected {}", name)))?;
+            Ok::<_, SnapshotError>((line, value.trim()))
+        };
+        let number = |(line, value): (usize, &str)| {
+            value
+                .parse::<i64>()
+                .map_err(|_| err(line, format!("invalid number {:?}", value)))
+        };
+        let list = |(line, value): (usize, &str)| -> Result<Vec<i64>, SnapshotError> {
+            if value.is_empty() {
+                return Ok(vec![]);
+            }
+            value.split(',').map(|item| number((line, item))).collect()
+        };
+        // Runs of zeros just move on, so they can't allocate more than the
+        // page at the end, and can't go past the last address an Intcode
+        // program could use
+        let memory = |(line, value): (usize, &str)| -> Result<Memory, SnapshotError> {
+            let mut memory = Memory::new();
+            if value.is_empty() {
+                return Ok(memory);
+            }
+            let mut addr = 0_usize;
+            for item in value.split(',') {
+                let n = match item.split_once('*') {
+                    Some(("0", n)) => n
+                        .parse()
+                        .map_err(|_| err(line, format!("invalid count {:?}", n)))?,
+                    Some(_) => return Err(err(line, format!("invalid run {:?}", item))),
+                    None => {
+                        memory.set(addr, number((line, item))?);
+                        1
+                    }
+                };
+                addr = addr
+                    .checked_add(n)
+                    .filter(|&addr| addr <= i64::MAX as usize)
+                    .ok_or_else(|| err(line, "memory too large".to_string()))?;
+            }
+            if addr > memory.len() {
+                memory.set(addr - 1, 0);
+            }
+            Ok(memory)
+        };
+
+        let (line, ptr) = field("ptr")?;
+        let ptr = usize::try_from(number((line, ptr))?)
+            .map_err(|_| err(line, "negative ptr".to_string()))?;
+        let relbase = number(field("relbase")?)?;
+        let halt_after_output = number(field("halt_after_output")?)? != 0;
+        let input = list(field("input")?)?;
+        let output = list(field("output")?)?;
+        let memory = memory(field("memory")?)?;
+
+        let mut intcode = Intcode::from_parts(memory, ptr, relbase);
+        intcode.halt_after_output(halt_after_output);
+        if version == 1 {
+            return Ok(Self {
+                intcode,
+                input,
+                output,
+            });
+        }
+
+        let (line, name) = field("arithmetic")?;
+        let arithmetic = [Arithmetic::Checked, Arithmetic::Wrapping, Arithmetic::Big]
+            .into_iter()
+            .find(|&a| arithmetic_name(a) == name)
+            .ok_or_else(|| err(line, format!("invalid arithmetic {:?}", name)))?;
+        intcode.set_arithmetic(arithmetic);
+        let limit = |(line, value): (usize, &str)| -> Result<Option<u64>, SnapshotError> {
+            if value.is_empty() {
+                return Ok(None);
+            }
+            let n = value
+                .parse()
+                .map_err(|_| err(line, format!("invalid limit {:?}", value)))?;
+            Ok(Some(n))
+        };
+        intcode.set_fuel(limit(field("fuel")?)?);
+        intcode.set_max_memory(limit(field("max_memory")?)?.map(|n| n as usize));
+
+        let (line, value) = field("instruction_set")?;
+        if !value.is_empty() {
+            let invalid = || err(line, format!("invalid instruction set {:?}", value));
+            let (instructions, modes) = value.split_once('/').ok_or_else(invalid)?;
+            let instructions: Vec<_> = instructions
+                .split(',')
+                .filter(|i| !i.is_empty())
+                .map(|i| Instruction::from_mnemonic(i).ok_or_else(invalid))
+                .collect::<Result<_, _>>()?;
+            let modes: Vec<_> = modes
+                .split(',')
+                .filter(|m| !m.is_empty())
+                .map(|m| {
+                    let digit = m.parse().map_err(|_| invalid())?;
+                    Mode::decode(digit)
+                        .filter(|mode| mode.digit() == digit)
+                        .ok_or_else(invalid)
+                })
+                .collect::<Result<_, _>>()?;
+            intcode.set_instruction_set(InstructionSet::new(&instructions, &modes));
+        }
+
+        let (line, value) = field("big")?;
+        for item in value.split(',').filter(|item| !item.is_empty()) {
+            let invalid = || err(line, format!("invalid big value {:?}", item));
+            let (addr, value) = item.split_once('=').ok_or_else(invalid)?;
+            let addr = addr
+                .parse()
+                .ok()
+                .filter(|&addr: &usize| addr <= i64::MAX as usize)
+                .ok_or_else(invalid)?;
+            let value: BigInt = value.parse().map_err(|_| invalid())?;
+            intcode.set_big(addr, value);
+        }
+        Ok(Self {
+            intcode,
+            input,
+            output,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_fn;
+
+    const QUINE: &str = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+
+    #[test]
+    fn test_format() {
+        let mut intcode = Intcode::new("3,0,0,0,0,0,4,0,99");
+        intcode.halt_after_output(true);
+        let snapshot = Snapshot::with_io(&intcode, &[1, 2], &[]);
+        assert_eq!(
+            snapshot.to_string(),
+            "intcode-snapshot 2
+ptr 0
+relbase 0
+halt_after_output 1
+input 1,2
+output
+memory 3,0*5,4,0,99
+arithmetic checked
+fuel
+max_memory
+instruction_set
+big
+"
+        );
+
+        // Version 1 stops after memory
+        let v1 = "intcode-snapshot 1\nptr 0\nrelbase 0\nhalt_after_output 1\ninput 1,2\noutput\nmemory 3,0*5,4,0,99";
+        let restored: Snapshot = v1.parse().unwrap();
+        assert_eq!(restored.to_string(), snapshot.to_string());
+    }
+
+    #[test]
+    fn test_machine_state() {
+        let mut intcode = Intcode::new("1102,4294967296,4294967296,7,4,8,99,0,5");
+        intcode.set_arithmetic(Arithmetic::Big);
+        intcode.set_instruction_set(InstructionSet::day5());
+        intcode.set_fuel(Some(10));
+        intcode.set_max_memory(Some(100));
+        intcode.run_for(|| None, |_| {}, 1);
+
+        let text = Snapshot::new(&intcode).to_string();
+        assert!(text.ends_with(
+            "arithmetic big
+fuel 9
+max_memory 100
+instruction_set add,mul,in,out,jt,jf,lt,eq,hlt/0,1
+big 7=18446744073709551616
+"
+        ));
+        let mut restored: Snapshot = text.parse().unwrap();
+        assert_eq!(restored.to_string(), text);
+        assert_eq!(restored.intcode.big_at(7), BigInt::from(2).pow(64));
+        assert_eq!(restored.intcode.run_simple::<i64>(&[]), Some(5));
+        assert_eq!(restored.intcode.fuel(), Some(8));
+
+        // Extension handlers are code, so can't be saved
+        intcode.set_instruction_set(InstructionSet::full().with(crate::Extension::exit(50)));
+        let path = std::env::temp_dir().join("intcode-snapshot-extension-test");
+        let err = Snapshot::new(&intcode).save(&path);
+        assert!(matches!(err, Err(SnapshotError::Unsupported(_))));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_roundtrip_mid_run() {
+        let mut intcode = Intcode::new(QUINE);
+        let mut output = vec![];
+        intcode.run_for(|| None, |v| output.push(v), 30);
+
+        let text = Snapshot::with_io(&intcode, &[], &output).to_string();
+        let restored: Snapshot = text.parse().unwrap();
+        assert_eq!(restored.to_string(), text);
+
+        let mut intcode = restored.intcode;
+        let mut output = restored.output;
+        intcode.run(|| None, |v| output.push(v));
+        assert_eq!(output, Intcode::new(QUINE).run_simplen::<i64>(&[]));
+    }
+
+    #[test]
+    fn test_restore_pending_input() {
+        let mut intcode = Intcode::new("3,11,3,12,1,11,12,13,4,13,99,0,0,0");
+        intcode.run(input_fn(&[20]), |_| {});
+        let snapshot: Snapshot = Snapshot::with_io(&intcode, &[22], &[])
+            .to_string()
+            .parse()
+            .unwrap();
+        let mut intcode = snapshot.intcode;
+        assert_eq!(intcode.run_simple(&snapshot.input), Some(42));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = "intcode-snapshot 1\nptr 0\nrelbase x".parse::<Snapshot>();
+        assert_eq!(
+            err.err().map(|e| e.to_string()),
+            Some("line 3: invalid number \"x\"".to_string())
+        );
+        let err = "something else".parse::<Snapshot>();
+        assert!(matches!(err, Err(SnapshotError::Format { line: 1, .. })));
+        let err = "intcode-snapshot 1\nptr 0\nrelbase 0".parse::<Snapshot>();
+        assert_eq!(
+            err.err().map(|e| e.to_string()),
+            Some("line 3: missing halt_after_output".to_string())
+        );
+
+        let header = "intcode-snapshot 1\nptr 0\nrelbase 0\nhalt_after_output 0\ninput\noutput";
+        for memory in ["0*99999999999999999999", "0*9223372036854775807,1", "5*3"] {
+            let err = format!("{}\nmemory {}", header, memory).parse::<Snapshot>();
+            assert!(matches!(err, Err(SnapshotError::Format { line: 7, .. })));
+        }
+    }
+
+    #[test]
+    fn test_sparse() {
+        let mut intcode = Intcode::new("109,1099511627776,21101,1,2,0,99");
+        intcode.run(|| None, |_| {});
+        let text = Snapshot::new(&intcode).to_string();
+        assert!(text.contains("\nmemory 109,1099511627776,21101,1,2,0,99,0*1099511627769,3\n"));
+        let restored: Snapshot = text.parse().unwrap();
+        assert_eq!(restored.intcode.memory(), intcode.memory());
+        assert_eq!(restored.to_string(), text);
+    }
+}
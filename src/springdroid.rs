@@ -0,0 +1,204 @@
+use crate::{Intcode, SpringError, SpringMode, Springscript};
+use std::collections::BTreeMap;
+
+// The hull a springdroid fell into, from what the program prints when it
+// does:
+//
+//   Didn't make it across:
+//
+//   .................
+//   .................
+//   @................
+//   #####.###########
+//
+// followed by more frames of it falling in. The first frame shows the droid
+// standing on the first tile.
+pub fn parse_hull(output: &str) -> Option<String> {
+    let (_, frames) = output.split_once("Didn't make it across:")?;
+    let frame: Vec<_> = frames
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| line.is_empty())
+        .take_while(|line| !line.is_empty())
+        .collect();
+    let hull = frame.last()?;
+    hull.chars()
+        .all(|c| c == '#' || c == '.')
+        .then(|| hull.to_string())
+}
+
+// Works out when a springdroid should jump by watching where it falls in:
+// each hull it fails on is added to those it has to cross, and a program is
+// synthesised from a choice of jumping or not for each sensor reading that
+// gets across all of them
+pub struct SpringLearner {
+    intcode: Intcode,
+    mode: SpringMode,
+    hulls: Vec<String>,
+    // Whether to jump for each sensor reading met crossing the hulls
+    cases: BTreeMap<u16, bool>,
+}
+
+impl SpringLearner {
+    pub fn new(intcode: Intcode, mode: SpringMode) -> Self {
+        Self {
+            intcode,
+            mode,
+            hulls: vec![],
+            cases: BTreeMap::new(),
+        }
+    }
+
+    pub fn hulls(&self) -> &[String] {
+        &self.hulls
+    }
+
+    pub fn cases(&self) -> &BTreeMap<u16, bool> {
+        &self.cases
+    }
+
+    // Runs the program, returning the hull damage reported, or the hull the
+    // droid fell into
+    pub fn attempt(&self, script: &Springscript) -> Result<i64, SpringError> {
+        let (output, damage) = self
+            .intcode
+            .clone()
+            .run_ascii_and_answer(&script.to_string());
+        if let Some(hull) = parse_hull(&output) {
+            return Err(SpringError::Fell(hull));
+        }
+        // The damage is too big to capture as a character
+        damage.ok_or(SpringError::Parse(output))
+    }
+
+    // Learns until the droid makes it across, returning the program and the
+    // hull damage it reported
+    pub fn solve(&mut self) -> Result<(Springscript, i64), SpringError> {
+        loop {
+            let script = Springscript::synthesise(&self.cases, self.mode)?;
+            match self.attempt(&script) {
+                Err(SpringError::Fell(hull)) if !self.hulls.contains(&hull) => {
+                    self.hulls.push(hull);
+                    self.learn()?;
+                }
+                result => return result.map(|damage| (script, damage)),
+            }
+        }
+    }
+
+    // Chooses whether to jump for each reading so every hull can be crossed,
+    // walking where possible
+    fn learn(&mut self) -> Result<(), SpringError> {
+        let hulls: Vec<Vec<bool>> = self
+            .hulls
+            .iter()
+            .map(|hull| hull.chars().map(|c| c == '#').collect())
+            .collect();
+        let mut cases = BTreeMap::new();
+        if !cross(&hulls, 0, self.mode.sensors(), &mut cases) {
+            return Err(SpringError::Inconsistent);
+        }
+        self.cases = cases;
+        Ok(())
+    }
+
+    // The truth table learned, as lines of what the sensors saw (# for
+    // ground, . for a hole) and whether to jump
+    pub fn table(&self) -> String {
+        let sensors = self.mode.sensors();
+        let mut table: String = (0..sensors).map(|i| (b'A' + i) as char).collect();
+        table.push_str("  jump\n");
+        for (&reading, &jump) in &self.cases {
+            table.extend((0..sensors).map(|i| match reading & (1 << i) {
+                0 => '.',
+                _ => '#',
+            }));
+            table.push_str(if jump { "  yes\n" } else { "  no\n" });
+        }
+        table
+    }
+}
+
+// Whether the first hull can be crossed from the given position, then the
+// rest, extending the cases as needed (backtracking over the choices made)
+fn cross(hulls: &[Vec<bool>], pos: usize, sensors: u8, cases: &mut BTreeMap<u16, bool>) -> bool {
+    let Some(hull) = hulls.first() else {
+        return true;
+    };
+    if pos >= hull.len() {
+        return cross(&hulls[1..], 0, sensors, cases);
+    }
+    let at = |pos: usize| hull.get(pos).copied().unwrap_or(true);
+    let reading = (0..sensors)
+        .filter(|&i| at(pos + 1 + i as usize))
+        .fold(0, |reading, i| reading | (1 << i));
+
+    let known = cases.get(&reading).copied();
+    for jump in [false, true] {
+        if known.is_some_and(|known| known != jump) {
+            continue;
+        }
+        let next = pos + if jump { 4 } else { 1 };
+        if !at(next) {
+            continue;
+        }
+        cases.insert(reading, jump);
+        if cross(hulls, next, sensors, cases) {
+            return true;
+        }
+        if known.is_none() {
+            cases.remove(&reading);
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hull() {
+        let code = crate::template::read_file("examples", crate::day!(21));
+        let output = Intcode::new(&code).run_ascii_and_capture("WALK\n");
+        assert_eq!(parse_hull(&output), Some("#####.###########".to_string()));
+        assert_eq!(parse_hull("Input instructions:\n"), None);
+    }
+
+    #[test]
+    fn test_solve() {
+        let code = crate::template::read_file("examples", crate::day!(21));
+        for (mode, damage) in [(SpringMode::Walk, 19350375), (SpringMode::Run, 1143990055)] {
+            let mut learner = SpringLearner::new(Intcode::new(&code), mode);
+            let (script, result) = learner.solve().unwrap();
+            assert_eq!(result, damage);
+            for hull in learner.hulls() {
+                assert_eq!(script.simulate(hull), Ok(()), "{}", hull);
+            }
+            for (&reading, &jump) in learner.cases() {
+                assert_eq!(script.eval(reading), jump);
+            }
+            let table = learner.table();
+            assert_eq!(table.lines().count(), learner.cases().len() + 1);
+            // Jumping lands on D
+            for line in table.lines().filter(|line| line.ends_with("yes")) {
+                assert_eq!(line.chars().nth(3), Some('#'), "{}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cross() {
+        // The only way across is to jump from the first tile, then walk
+        let hull: Vec<_> = "#...#".chars().map(|c| c == '#').collect();
+        let mut cases = BTreeMap::new();
+        assert!(cross(&[hull], 0, 4, &mut cases));
+        assert_eq!(cases, BTreeMap::from([(0b1000, true), (0b1111, false)]));
+
+        // Walking or jumping from the first tile both fall in
+        let hull: Vec<_> = "#.##.####".chars().map(|c| c == '#').collect();
+        let mut cases = BTreeMap::new();
+        assert!(!cross(&[hull], 0, 4, &mut cases));
+        assert!(cases.is_empty());
+    }
+}
@@ -0,0 +1,808 @@
+use hashbrown::HashSet;
+use itertools::Itertools;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+// Most instructions a springdroid accepts
+pub const MAX_INSTRUCTIONS: usize = 15;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SpringError {
+    Parse(String),
+    // Sensor out of range for the mode (WALK only has A-D)
+    Sensor(char),
+    // Number of instructions needed
+    TooLong(usize),
+    // The hull the droid fell into
+    Fell(String),
+    // No choice of when to jump gets across all the hulls
+    Inconsistent,
+}
+
+impl fmt::Display for SpringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpringError::Parse(message) => write!(f, "Parse error: {}", message),
+            SpringError::Sensor(sensor) => write!(f, "Sensor {} not available", sensor),
+            SpringError::TooLong(len) => write!(
+                f,
+                "Program needs {} instructions (at most {} allowed)",
+                len, MAX_INSTRUCTIONS
+            ),
+            SpringError::Fell(hull) => write!(f, "Fell into a hole in {}", hull),
+            SpringError::Inconsistent => write!(f, "No way of jumping crosses every hull"),
+        }
+    }
+}
+
+impl std::error::Error for SpringError {}
+
+// A boolean expression over the sensors, written like `(!b || !c) && d`
+// (sensors a to i, in either case, with ! && || and parentheses)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Expr {
+    Const(bool),
+    Sensor(u8), // 0 for A
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    // Sensor readings have bit i set when sensor i sees ground
+    pub fn eval(&self, sensors: u16) -> bool {
+        match self {
+            Expr::Const(value) => *value,
+            Expr::Sensor(i) => sensors & (1 << i) != 0,
+            Expr::Not(e) => !e.eval(sensors),
+            Expr::And(a, b) => a.eval(sensors) && b.eval(sensors),
+            Expr::Or(a, b) => a.eval(sensors) || b.eval(sensors),
+        }
+    }
+
+    // Bit mask of the sensors used
+    pub fn sensors(&self) -> u16 {
+        match self {
+            Expr::Const(_) => 0,
+            Expr::Sensor(i) => 1 << i,
+            Expr::Not(e) => e.sensors(),
+            Expr::And(a, b) | Expr::Or(a, b) => a.sensors() | b.sensors(),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = SpringError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens: Vec<_> = s.chars().filter(|c| !c.is_whitespace()).collect();
+        let mut parser = ExprParser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(c) => Err(parser.error(c)),
+        }
+    }
+}
+
+struct ExprParser {
+    tokens: Vec<char>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn peek(&self) -> Option<char> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn error(&self, c: char) -> SpringError {
+        SpringError::Parse(format!("unexpected {:?} at {}", c, self.pos))
+    }
+
+    // Takes an operator, written singly or doubled (e.g. | or ||)
+    fn operator(&mut self, op: char) -> bool {
+        if self.peek() != Some(op) {
+            return false;
+        }
+        self.pos += 1;
+        if self.peek() == Some(op) {
+            self.pos += 1;
+        }
+        true
+    }
+
+    fn or(&mut self) -> Result<Expr, SpringError> {
+        let mut expr = self.and()?;
+        while self.operator('|') {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, SpringError> {
+        let mut expr = self.unary()?;
+        while self.operator('&') {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, SpringError> {
+        let Some(c) = self.peek() else {
+            return Err(SpringError::Parse("unexpected end".to_string()));
+        };
+        self.pos += 1;
+        match c.to_ascii_uppercase() {
+            '!' => Ok(Expr::Not(Box::new(self.unary()?))),
+            '(' => {
+                let expr = self.or()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.pos += 1;
+                        Ok(expr)
+                    }
+                    Some(c) => Err(self.error(c)),
+                    None => Err(SpringError::Parse("missing )".to_string())),
+                }
+            }
+            '0' => Ok(Expr::Const(false)),
+            '1' => Ok(Expr::Const(true)),
+            c @ 'A'..='I' => Ok(Expr::Sensor(c as u8 - b'A')),
+            _ => {
+                self.pos -= 1;
+                Err(self.error(c))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpringMode {
+    Walk,
+    Run,
+}
+
+impl SpringMode {
+    pub fn sensors(&self) -> u8 {
+        match self {
+            SpringMode::Walk => 4,
+            SpringMode::Run => 9,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Reg {
+    Sensor(u8),
+    T,
+    J,
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reg::Sensor(i) => write!(f, "{}", (b'A' + i) as char),
+            Reg::T => write!(f, "T"),
+            Reg::J => write!(f, "J"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpringOp {
+    And,
+    Or,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SpringInstruction {
+    pub op: SpringOp,
+    pub x: Reg,
+    pub y: Reg, // T or J
+}
+
+impl fmt::Display for SpringInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.op {
+            SpringOp::And => "AND",
+            SpringOp::Or => "OR",
+            SpringOp::Not => "NOT",
+        };
+        write!(f, "{} {} {}", op, self.x, self.y)
+    }
+}
+
+// A springdroid program, written out as the droid expects it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Springscript {
+    pub instructions: Vec<SpringInstruction>,
+    pub mode: SpringMode,
+}
+
+impl fmt::Display for Springscript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for instruction in &self.instructions {
+            writeln!(f, "{}", instruction)?;
+        }
+        match self.mode {
+            SpringMode::Walk => writeln!(f, "WALK"),
+            SpringMode::Run => writeln!(f, "RUN"),
+        }
+    }
+}
+
+impl FromStr for Springscript {
+    type Err = SpringError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |line: &str| SpringError::Parse(format!("invalid instruction {:?}", line));
+        let reg = |name: &str, line: &str| match name {
+            "T" => Ok(Reg::T),
+            "J" => Ok(Reg::J),
+            _ => match name.as_bytes() {
+                [c @ b'A'..=b'I'] => Ok(Reg::Sensor(c - b'A')),
+                _ => Err(err(line)),
+            },
+        };
+        let mut instructions = vec![];
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let mode = match line {
+                "WALK" => Some(SpringMode::Walk),
+                "RUN" => Some(SpringMode::Run),
+                _ => None,
+            };
+            if let Some(mode) = mode {
+                return Springscript::new(instructions, mode);
+            }
+            let [op, x, y] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                return Err(err(line));
+            };
+            let op = match op {
+                "AND" => SpringOp::And,
+                "OR" => SpringOp::Or,
+                "NOT" => SpringOp::Not,
+                _ => return Err(err(line)),
+            };
+            let (x, y) = (reg(x, line)?, reg(y, line)?);
+            if matches!(y, Reg::Sensor(_)) {
+                return Err(err(line));
+            }
+            instructions.push(SpringInstruction { op, x, y });
+        }
+        Err(SpringError::Parse("missing WALK or RUN".to_string()))
+    }
+}
+
+impl Springscript {
+    // Checks the program fits in the droid, with sensors it has in the mode
+    pub fn new(
+        instructions: Vec<SpringInstruction>,
+        mode: SpringMode,
+    ) -> Result<Self, SpringError> {
+        if instructions.len() > MAX_INSTRUCTIONS {
+            return Err(SpringError::TooLong(instructions.len()));
+        }
+        for instruction in &instructions {
+            if let Reg::Sensor(i) = instruction.x {
+                if i >= mode.sensors() {
+                    return Err(SpringError::Sensor((b'A' + i) as char));
+                }
+            }
+        }
+        Ok(Self { instructions, mode })
+    }
+
+    // Compiles the expression for when to jump, minimised as both a sum of
+    // products and a product of sums, keeping whichever is shorter
+    pub fn compile(expr: &Expr, mode: SpringMode) -> Result<Self, SpringError> {
+        if let Some(i) = (mode.sensors()..9).find(|i| expr.sensors() & (1 << i) != 0) {
+            return Err(SpringError::Sensor((b'A' + i) as char));
+        }
+        let sensors: Vec<u8> = (0..9).filter(|i| expr.sensors() & (1 << i) != 0).collect();
+        let table: Vec<_> = (0..1_u16 << sensors.len())
+            .map(|row| Some(expr.eval(reading(&sensors, row))))
+            .collect();
+        Springscript::from_table(&sensors, &table, mode)
+    }
+
+    // As above, from whether to jump for some sensor readings, leaving the
+    // rest to whatever's shortest
+    pub fn synthesise(cases: &BTreeMap<u16, bool>, mode: SpringMode) -> Result<Self, SpringError> {
+        let sensors: Vec<u8> = (0..mode.sensors()).collect();
+        let mut table = vec![None; 1 << sensors.len()];
+        for (&reading, &jump) in cases {
+            *table.get_mut(reading as usize).ok_or(SpringError::Sensor(
+                (b'A' + 15 - reading.leading_zeros() as u8) as char,
+            ))? = Some(jump);
+        }
+        Springscript::from_table(&sensors, &table, mode)
+    }
+
+    fn from_table(
+        sensors: &[u8],
+        table: &[Option<bool>],
+        mode: SpringMode,
+    ) -> Result<Self, SpringError> {
+        let negated: Vec<_> = table.iter().map(|value| value.map(|v| !v)).collect();
+        let sop = emit(&minimise(sensors, table), true);
+        let pos = emit(&minimise(sensors, &negated), false);
+        let instructions = if pos.len() < sop.len() { pos } else { sop };
+        Springscript::new(instructions, mode)
+    }
+
+    // Whether the droid jumps given the sensor readings
+    pub fn eval(&self, sensors: u16) -> bool {
+        let (mut t, mut j) = (false, false);
+        for instruction in &self.instructions {
+            let x = match instruction.x {
+                Reg::Sensor(i) => sensors & (1 << i) != 0,
+                Reg::T => t,
+                Reg::J => j,
+            };
+            let y = if instruction.y == Reg::T {
+                &mut t
+            } else {
+                &mut j
+            };
+            *y = match instruction.op {
+                SpringOp::And => x && *y,
+                SpringOp::Or => x || *y,
+                SpringOp::Not => !x,
+            };
+        }
+        j
+    }
+
+    // Runs the droid along a hull (# for ground, . for a hole, starting on
+    // the first tile), returning where it fell in if it did. Beyond the end
+    // is all ground.
+    pub fn simulate(&self, hull: &str) -> Result<(), usize> {
+        let ground: Vec<_> = hull.chars().map(|c| c != '.').collect();
+        let at = |pos: usize| ground.get(pos).copied().unwrap_or(true);
+        let mut pos = 0;
+        while pos < ground.len() {
+            let sensors = (0..self.mode.sensors())
+                .filter(|i| at(pos + 1 + *i as usize))
+                .fold(0, |sensors, i| sensors | (1 << i));
+            pos += if self.eval(sensors) { 4 } else { 1 };
+            if !at(pos) {
+                return Err(pos);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Sensor readings for a row of a truth table over the given sensors
+fn reading(sensors: &[u8], row: u16) -> u16 {
+    sensors
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| row & (1 << bit) != 0)
+        .fold(0, |reading, (_, i)| reading | (1 << i))
+}
+
+// A product of sensors, each read as is (true) or negated
+type Term = Vec<(u8, bool)>;
+
+// Minimal sum of products (by Quine-McCluskey, covering greedily after the
+// essential prime implicants) for a truth table, where None is a row that
+// can go either way
+fn minimise(sensors: &[u8], table: &[Option<bool>]) -> Vec<Term> {
+    let rows = |wanted: &[Option<bool>]| -> Vec<u16> {
+        (0..table.len() as u16)
+            .filter(|&row| wanted.contains(&table[row as usize]))
+            .collect()
+    };
+    let minterms = rows(&[Some(true)]);
+
+    // Implicants as (values, don't care mask), merged until they won't
+    let mut primes = vec![];
+    let mut implicants: HashSet<(u16, u16)> = rows(&[Some(true), None])
+        .into_iter()
+        .map(|m| (m, 0))
+        .collect();
+    while !implicants.is_empty() {
+        let mut next = HashSet::new();
+        for &(value, mask) in &implicants {
+            let mut merged = false;
+            for bit in (0..sensors.len()).map(|bit| 1 << bit) {
+                if mask & bit == 0 && implicants.contains(&(value ^ bit, mask)) {
+                    next.insert((value & !bit, mask | bit));
+                    merged = true;
+                }
+            }
+            if !merged {
+                primes.push((value, mask));
+            }
+        }
+        implicants = next;
+    }
+    // Larger implicants first, for a covering that doesn't depend on the order
+    // of the set
+    primes.sort_by_key(|&(value, mask)| (std::cmp::Reverse(mask.count_ones()), value, mask));
+
+    let covers = |(value, mask): (u16, u16), m: u16| m & !mask == value;
+    let mut uncovered = minterms.clone();
+    let mut chosen = vec![];
+    for &m in &minterms {
+        let covering: Vec<_> = primes.iter().filter(|&&p| covers(p, m)).collect();
+        if let [&essential] = covering[..] {
+            if !chosen.contains(&essential) {
+                chosen.push(essential);
+            }
+        }
+    }
+    uncovered.retain(|&m| !chosen.iter().any(|&p| covers(p, m)));
+    while !uncovered.is_empty() {
+        let best = *primes
+            .iter()
+            .max_by_key(|&&p| {
+                let count = uncovered.iter().filter(|&&m| covers(p, m)).count();
+                (count, p.1.count_ones())
+            })
+            .unwrap();
+        chosen.push(best);
+        uncovered.retain(|&m| !covers(best, m));
+    }
+
+    chosen
+        .into_iter()
+        .map(|(value, mask)| {
+            (0..sensors.len())
+                .filter(|bit| mask & (1 << bit) == 0)
+                .map(|bit| (sensors[bit], value & (1 << bit) != 0))
+                .collect()
+        })
+        .collect()
+}
+
+// How to compute a sum of products: as it is, or with common literals
+// factored out (e.g. a & b | a & c as a & (b | c))
+#[derive(Clone, Debug)]
+enum Tree {
+    Literal(u8, bool),
+    And(Vec<Tree>),
+    Or(Vec<Tree>),
+}
+
+impl Tree {
+    fn product(term: &[(u8, bool)]) -> Tree {
+        match term {
+            [(sensor, positive)] => Tree::Literal(*sensor, *positive),
+            _ => Tree::And(term.iter().map(|&(s, p)| Tree::Literal(s, p)).collect()),
+        }
+    }
+
+    fn sum(terms: &[Term]) -> Tree {
+        match terms {
+            [term] => Tree::product(term),
+            _ => Tree::Or(terms.iter().map(|term| Tree::product(term)).collect()),
+        }
+    }
+
+    fn factor(terms: &[Term]) -> Tree {
+        let counts = terms.iter().flatten().counts();
+        let Some((&&common, &count)) = counts.iter().max_by_key(|(l, count)| (**count, **l)) else {
+            return Tree::sum(terms);
+        };
+        if count < 2 {
+            return Tree::sum(terms);
+        }
+        let (with, without): (Vec<Term>, Vec<Term>) = terms
+            .iter()
+            .cloned()
+            .partition(|term| term.contains(&common));
+        let rest: Vec<Term> = with
+            .into_iter()
+            .map(|term| term.into_iter().filter(|l| *l != common).collect())
+            .collect();
+        let factored = Tree::And(vec![Tree::Literal(common.0, common.1), Tree::factor(&rest)]);
+        match Tree::factor(&without) {
+            _ if without.is_empty() => factored,
+            Tree::Or(mut children) => {
+                children.insert(0, factored);
+                Tree::Or(children)
+            }
+            tree => Tree::Or(vec![factored, tree]),
+        }
+    }
+
+    fn is_literal(&self) -> bool {
+        matches!(self, Tree::Literal(..))
+    }
+
+    // Computable in one register
+    fn is_simple(&self) -> bool {
+        match self {
+            Tree::Literal(..) => true,
+            Tree::And(children) | Tree::Or(children) => children.iter().all(Tree::is_literal),
+        }
+    }
+}
+
+// A register's value as far as the compiler knows: either holding what it
+// should, or its negation (which saves instructions), or known to be false
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Held {
+    False,
+    Value,
+    Negated,
+}
+
+#[derive(Clone)]
+struct Emitter {
+    instructions: Vec<SpringInstruction>,
+    t: Held,
+    j: Held,
+}
+
+// Keeps the shortest way of reaching each state of the registers
+fn shortest(emitters: Vec<Emitter>) -> Vec<Emitter> {
+    emitters
+        .into_iter()
+        .into_group_map_by(|e| (e.t, e.j))
+        .into_values()
+        .filter_map(|group| group.into_iter().min_by_key(|e| e.instructions.len()))
+        .collect()
+}
+
+impl Emitter {
+    fn push(&mut self, op: SpringOp, x: Reg, y: Reg) {
+        self.instructions.push(SpringInstruction { op, x, y });
+    }
+
+    fn held(&mut self, reg: Reg) -> &mut Held {
+        if reg == Reg::T {
+            &mut self.t
+        } else {
+            &mut self.j
+        }
+    }
+
+    fn operand(&self, reg: Reg) -> (Reg, bool) {
+        let held = if reg == Reg::T { self.t } else { self.j };
+        (reg, held != Held::Negated)
+    }
+
+    // Ways of setting the register to the operand (read as is or negated):
+    // with NOT, or with OR when the register is known to be false
+    fn load(&self, reg: Reg, (x, positive): (Reg, bool)) -> Vec<Emitter> {
+        let mut options = vec![];
+        let mut emitter = self.clone();
+        emitter.push(SpringOp::Not, x, reg);
+        *emitter.held(reg) = if positive { Held::Negated } else { Held::Value };
+        options.push(emitter);
+        if *self.clone().held(reg) == Held::False {
+            let mut emitter = self.clone();
+            emitter.push(SpringOp::Or, x, reg);
+            *emitter.held(reg) = if positive { Held::Value } else { Held::Negated };
+            options.push(emitter);
+        }
+        options
+    }
+
+    // Combines the operand into the register. With it negated, a | !x is
+    // !(!a & x), so when the register is held negated a negated operand
+    // goes straight in, as does an operand as is into a register that is.
+    // Otherwise either the register is flipped first, or the operand is
+    // (through T, for a sensor).
+    fn combine(&self, op: SpringOp, reg: Reg, (x, positive): (Reg, bool)) -> Vec<Emitter> {
+        let mut options = vec![];
+        let mut emitter = self.clone();
+        let negated = *emitter.held(reg) == Held::Negated;
+        if negated == positive {
+            emitter.push(SpringOp::Not, reg, reg);
+            let mut flipped = self.clone();
+            match x {
+                Reg::T => {
+                    flipped.push(SpringOp::Not, Reg::T, Reg::T);
+                    options.extend(flipped.combine(op, reg, (x, !positive)));
+                }
+                Reg::Sensor(_) if reg == Reg::J => {
+                    flipped.push(SpringOp::Not, x, Reg::T);
+                    flipped.t = Held::Value;
+                    options.extend(flipped.combine(op, reg, (Reg::T, !positive)));
+                }
+                _ => {}
+            }
+        }
+        let inverse = match op {
+            SpringOp::And => SpringOp::Or,
+            _ => SpringOp::And,
+        };
+        emitter.push(if positive { op } else { inverse }, x, reg);
+        *emitter.held(reg) = if positive { Held::Value } else { Held::Negated };
+        options.push(emitter);
+        options
+    }
+
+    // Ways of putting the tree's value (or its negation) into the register.
+    // It has to be a chain: at most one operand at each level that isn't
+    // simple, computed first, with the simple ones that aren't literals
+    // computed in T. None if it isn't.
+    fn tree(&self, reg: Reg, tree: &Tree) -> Option<Vec<Emitter>> {
+        let (op, children) = match tree {
+            Tree::Literal(sensor, positive) => {
+                return Some(self.load(reg, (Reg::Sensor(*sensor), *positive)));
+            }
+            Tree::And(children) => (SpringOp::And, children),
+            Tree::Or(children) => (SpringOp::Or, children),
+        };
+        if (reg == Reg::T && !tree.is_simple())
+            || children.iter().filter(|c| !c.is_simple()).count() > 1
+        {
+            return None;
+        }
+        let head = children
+            .iter()
+            .position(|c| !c.is_simple())
+            .or_else(|| children.iter().position(|c| !c.is_literal()))
+            .unwrap_or(0);
+        let rest: Vec<_> = (0..children.len())
+            .filter(|&i| i != head)
+            .map(|i| &children[i])
+            .collect();
+
+        // Literals grouped by sign need fewer flips
+        let sign = |tree: &Tree| match tree {
+            Tree::Literal(_, positive) => Some(*positive),
+            _ => None,
+        };
+        let mut options = vec![];
+        for (negative_first, literals_first) in [(false, true), (true, true), (false, false)] {
+            let order = rest.iter().sorted_by_key(|c| {
+                (
+                    c.is_literal() != literals_first,
+                    sign(c) != Some(negative_first),
+                )
+            });
+            let mut emitters = self.tree(reg, &children[head])?;
+            for child in order {
+                let mut next = vec![];
+                for emitter in emitters {
+                    match child {
+                        Tree::Literal(sensor, positive) => {
+                            next.extend(emitter.combine(op, reg, (Reg::Sensor(*sensor), *positive)))
+                        }
+                        _ => {
+                            for emitter in emitter.tree(Reg::T, child)? {
+                                next.extend(emitter.combine(op, reg, emitter.operand(Reg::T)));
+                            }
+                        }
+                    }
+                }
+                emitters = shortest(next);
+            }
+            options.extend(emitters);
+        }
+        Some(shortest(options))
+    }
+}
+
+// Instructions leaving J set to the sum of the products, or to its negation,
+// trying it both factored and not
+fn emit(terms: &[Term], positive: bool) -> Vec<SpringInstruction> {
+    let start = Emitter {
+        instructions: vec![],
+        t: Held::False,
+        j: Held::False,
+    };
+    let options = if terms.iter().any(|term| term.is_empty()) {
+        // Always true
+        let mut emitter = start;
+        emitter.push(SpringOp::Not, Reg::T, Reg::J);
+        emitter.j = Held::Value;
+        vec![emitter]
+    } else if terms.is_empty() {
+        vec![start]
+    } else {
+        [Tree::sum(terms), Tree::factor(terms)]
+            .iter()
+            .filter_map(|tree| start.tree(Reg::J, tree))
+            .flatten()
+            .collect()
+    };
+
+    let wanted = if positive { Held::Value } else { Held::Negated };
+    options
+        .into_iter()
+        .map(|mut emitter| {
+            match emitter.j {
+                // Never jumping (or always, when negated)
+                Held::False if positive => {}
+                held if held != wanted => emitter.push(SpringOp::Not, Reg::J, Reg::J),
+                _ => {}
+            }
+            emitter.instructions
+        })
+        .min_by_key(|instructions| instructions.len())
+        .expect("Sums of products can always be emitted")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(expr: &str, mode: SpringMode) -> Springscript {
+        let expr: Expr = expr.parse().unwrap();
+        let script = Springscript::compile(&expr, mode).unwrap();
+        for sensors in 0..1 << mode.sensors() {
+            assert_eq!(script.eval(sensors), expr.eval(sensors), "{}", script);
+        }
+        assert_eq!(
+            script.to_string().parse::<Springscript>(),
+            Ok(script.clone())
+        );
+        script
+    }
+
+    #[test]
+    fn test_compile() {
+        let script = check("(!b || !c) && d || !a", SpringMode::Walk);
+        assert!(script.instructions.len() <= 6, "{}", script);
+        check("(!b || !c) && d && h || !a", SpringMode::Run);
+        for expr in [
+            "0",
+            "1",
+            "a",
+            "!a",
+            "a && b && c",
+            "!a && !b",
+            "a && !b || !c && d",
+        ] {
+            check(expr, SpringMode::Walk);
+        }
+        check("(a || b) && (c || d) && (e || f)", SpringMode::Run);
+    }
+
+    #[test]
+    fn test_errors() {
+        // Whether an odd number of A to D are ground, which two registers
+        // can't hold in 15 instructions
+        let parity: Expr = (0..16_u32)
+            .filter(|row| row.count_ones() % 2 == 1)
+            .map(|row| {
+                (0..4)
+                    .map(|i| match row & (1 << i) {
+                        0 => format!("!{}", (b'a' + i) as char),
+                        _ => ((b'a' + i) as char).to_string(),
+                    })
+                    .join(" && ")
+            })
+            .join(" || ")
+            .parse()
+            .unwrap();
+        assert!(matches!(
+            Springscript::compile(&parity, SpringMode::Walk),
+            Err(SpringError::TooLong(_))
+        ));
+        let e: Expr = "a && e".parse().unwrap();
+        assert_eq!(
+            Springscript::compile(&e, SpringMode::Walk),
+            Err(SpringError::Sensor('E'))
+        );
+        assert!("a &&".parse::<Expr>().is_err());
+        assert!("(a || b".parse::<Expr>().is_err());
+        assert!("NOT A B\nWALK".parse::<Springscript>().is_err());
+    }
+
+    #[test]
+    fn test_simulate() {
+        let never: Springscript = "WALK".parse().unwrap();
+        assert_eq!(never.simulate("#####.###"), Err(5));
+        let script = check("(!b || !c) && d || !a", SpringMode::Walk);
+        for hull in [
+            "#####.###########",
+            "#####...#########",
+            "#####..#.########",
+        ] {
+            assert_eq!(script.simulate(hull), Ok(()), "{}", hull);
+        }
+        // Jumping early over the first hole leaves nowhere to land after the
+        // second
+        assert_eq!(script.simulate("#####.#.#...#.###"), Err(10));
+    }
+}
@@ -0,0 +1,262 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::{fs, process};
+
+use crate::template::{Day, ANSI_BOLD, ANSI_RESET};
+use crate::{Debugger, Intcode, Snapshot, Stop};
+
+const HELP: &str = "\
+Commands:
+  s, step [n]           execute n instructions (default 1)
+  c, continue [n]       run until a breakpoint, watchpoint or stop (at most n steps)
+  rs, rstep [n]         step back n instructions (default 1)
+  rw, rwatch <addr>     step back to before the last write to addr
+  ro, rout              step back to before the last output
+  b, break [addr]       set a breakpoint (or list breakpoints)
+  d, delete <addr>      remove a breakpoint
+  w, watch [addr]       watch an address for changes (or list watchpoints)
+  dw, unwatch <addr>    stop watching an address
+  r, regs               show the instruction pointer, relative base and step count
+  l, list [addr] [n]    disassemble n instructions from addr (default: current)
+  x <addr> [n]          examine n memory values from addr (default 8, at most 4096)
+  set <addr> <value>    write a value to memory
+  in <values...>        queue input values
+  in \"text\"             queue text as ASCII (escape a newline as \\n)
+  in clear              clear queued input
+  out [clear]           show (or clear) the output so far
+  save <file>           save the machine state (including queued I/O) to a file
+  load <file>           restore the machine state from a saved file
+  h, help               show this help
+  q, quit               exit";
+
+// Most memory values the x command will show at once
+const MAX_VALUES: usize = 4096;
+
+// Program is either a path or a day whose input should be loaded
+pub fn handle(program: &str) {
+    let (path, intcode) = load(program);
+    println!(
+        "Loaded {} values from \"{path}\". Type `help` for commands.",
+        intcode.len()
+    );
+    let mut debugger = Debugger::new(intcode);
+    show_current(&debugger);
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("{ANSI_BOLD}(icdb){ANSI_RESET} ");
+        let _ = io::stdout().flush();
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+        match execute(&mut debugger, line.trim()) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+// Reads and parses the program (exiting if that fails), returning the path
+// it was read from
+pub(crate) fn load(program: &str) -> (String, Intcode) {
+    let path = match program.parse::<Day>() {
+        Ok(day) if !Path::new(program).exists() => format!("data/inputs/{day}.txt"),
+        _ => program.to_string(),
+    };
+
+    let code = match fs::read_to_string(&path) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Failed to read program \"{path}\": {e}");
+            process::exit(1);
+        }
+    };
+
+    let intcode = match Intcode::try_new(&code) {
+        Ok(intcode) => intcode,
+        Err(e) => {
+            eprintln!("Failed to parse program \"{path}\": {e}");
+            process::exit(1);
+        }
+    };
+
+    (path, intcode)
+}
+
+fn execute(debugger: &mut Debugger, line: &str) -> Result<bool, String> {
+    let (cmd, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let args: Vec<_> = rest.split_whitespace().collect();
+
+    match cmd {
+        "" => {}
+        "s" | "step" => {
+            let n = arg_or(&args, 0, 1)?;
+            let mut stop = Stop::Stepped;
+            for _ in 0..n {
+                stop = debugger.step();
+                if stop != Stop::Stepped {
+                    break;
+                }
+            }
+            show_stop(debugger, &stop);
+        }
+        "c" | "continue" => {
+            let n = arg_or(&args, 0, usize::MAX)?;
+            let stop = debugger.cont(n);
+            show_stop(debugger, &stop);
+        }
+        "rs" | "rstep" => {
+            let n = arg_or(&args, 0, 1)?;
+            let mut stop = Stop::Stepped;
+            for _ in 0..n {
+                if debugger.step_back().is_none() {
+                    stop = Stop::Start;
+                    break;
+                }
+            }
+            show_stop(debugger, &stop);
+        }
+        "rw" | "rwatch" => {
+            let stop = debugger.reverse_to_write(arg(&args, 0)?);
+            show_stop(debugger, &stop);
+        }
+        "ro" | "rout" => {
+            let stop = debugger.reverse_to_output();
+            show_stop(debugger, &stop);
+        }
+        "b" | "break" => match args.first() {
+            Some(_) => {
+                debugger.add_breakpoint(arg(&args, 0)?);
+            }
+            None => println!("Breakpoints: {:?}", debugger.breakpoints()),
+        },
+        "d" | "delete" => {
+            if !debugger.remove_breakpoint(arg(&args, 0)?) {
+                return Err("No such breakpoint".to_string());
+            }
+        }
+        "w" | "watch" => match args.first() {
+            Some(_) => {
+                debugger.add_watchpoint(arg(&args, 0)?);
+            }
+            None => println!("Watchpoints: {:?}", debugger.watchpoints()),
+        },
+        "dw" | "unwatch" => {
+            if !debugger.remove_watchpoint(arg(&args, 0)?) {
+                return Err("No such watchpoint".to_string());
+            }
+        }
+        "r" | "regs" => show_regs(debugger),
+        "l" | "list" => {
+            let addr = arg_or(&args, 0, debugger.intcode.ptr())?;
+            let n = arg_or(&args, 1, 10)?;
+            for line in debugger.listing(addr, n) {
+                println!("{line}");
+            }
+        }
+        "x" => {
+            let addr: usize = arg(&args, 0)?;
+            let n = arg_or(&args, 1, 8)?;
+            if n > MAX_VALUES {
+                return Err(format!("At most {MAX_VALUES} values can be shown"));
+            }
+            let memory = debugger.memory(addr, n).ok_or("Address out of range")?;
+            for (row, values) in memory.chunks(8).enumerate() {
+                let values: Vec<_> = values.iter().map(|v| format!("{v:>8}")).collect();
+                println!("{:04}: {}", addr + row * 8, values.join(" "));
+            }
+        }
+        "set" => {
+            let addr = arg(&args, 0)?;
+            let value = arg(&args, 1)?;
+            debugger.intcode.set_code(addr, value);
+        }
+        "in" => {
+            if rest.trim() == "clear" {
+                debugger.input.clear();
+            } else if let Some(text) = rest.trim().strip_prefix('"') {
+                let text = text.strip_suffix('"').ok_or("Unterminated string")?;
+                debugger.push_ascii(&text.replace("\\n", "\n"));
+            } else {
+                let values = (0..args.len())
+                    .map(|i| arg(&args, i))
+                    .collect::<Result<Vec<i64>, _>>()?;
+                debugger.push_input(values);
+            }
+            println!("Input: {:?}", debugger.input);
+        }
+        "out" => {
+            if args.first() == Some(&"clear") {
+                debugger.output.clear();
+            } else {
+                show_output(&debugger.output);
+            }
+        }
+        "save" => {
+            let path = args.first().ok_or("Missing file")?;
+            debugger.snapshot().save(path).map_err(|e| e.to_string())?;
+            println!("Saved to \"{path}\"");
+        }
+        "load" => {
+            let path = args.first().ok_or("Missing file")?;
+            let snapshot = Snapshot::load(path).map_err(|e| e.to_string())?;
+            debugger.restore(snapshot);
+            show_regs(debugger);
+            show_current(debugger);
+        }
+        "h" | "help" => println!("{HELP}"),
+        "q" | "quit" => return Ok(false),
+        _ => return Err(format!("Unknown command: {cmd} (type `help` for commands)")),
+    }
+    Ok(true)
+}
+
+fn arg<T: std::str::FromStr>(args: &[&str], i: usize) -> Result<T, String> {
+    let value = args.get(i).ok_or("Missing argument")?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid argument: {value}"))
+}
+
+fn arg_or<T: std::str::FromStr>(args: &[&str], i: usize, default: T) -> Result<T, String> {
+    if i < args.len() {
+        arg(args, i)
+    } else {
+        Ok(default)
+    }
+}
+
+fn show_stop(debugger: &Debugger, stop: &Stop) {
+    if *stop != Stop::Stepped {
+        println!("{stop}");
+    }
+    show_current(debugger);
+}
+
+fn show_current(debugger: &Debugger) {
+    for line in debugger.listing(debugger.intcode.ptr(), 1) {
+        println!("{line}");
+    }
+}
+
+fn show_regs(debugger: &Debugger) {
+    println!(
+        "ptr={} relbase={} steps={} halted={}",
+        debugger.intcode.ptr(),
+        debugger.intcode.relbase(),
+        debugger.steps(),
+        debugger.intcode.is_halted()
+    );
+    println!("Input: {:?}", debugger.input);
+    println!("Output: {} value(s)", debugger.output.len());
+}
+
+fn show_output(output: &[i64]) {
+    println!("{output:?}");
+    if output.iter().all(|v| (0..128).contains(v)) && !output.is_empty() {
+        let text: String = output.iter().map(|v| (*v as u8) as char).collect();
+        println!("{text}");
+    }
+}
@@ -1,5 +1,7 @@
 pub mod all;
+pub mod debug;
 pub mod download;
+pub mod play;
 pub mod read;
 pub mod scaffold;
 pub mod solve;
@@ -0,0 +1,102 @@
+use std::io::{self, BufRead, Write};
+
+use super::debug::load;
+use crate::template::{ANSI_BOLD, ANSI_ITALIC, ANSI_RESET};
+use crate::{AsciiConsole, AsciiOutput, IntcodeError, RunState};
+
+const HELP: &str = "\
+Lines are sent to the program as typed, apart from these:
+  !!                    repeat the last line
+  !<n>                  repeat line n from the history
+  :history              list the lines sent so far
+  :save <file>          save a transcript of the session
+  :help                 show this help
+  :quit                 exit";
+
+// Connects an ASCII-capable program (a path, or a day whose input should be
+// loaded) to the terminal. Once the program stops (or fails) the session can
+// still be saved.
+pub fn handle(program: &str) {
+    let (path, intcode) = load(program);
+    println!("{ANSI_ITALIC}Loaded \"{path}\". Type `:help` for commands.{ANSI_RESET}");
+    let mut console = AsciiConsole::new(intcode);
+    let (output, mut state) = console.run();
+    show(&output, &state);
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        let _ = io::stdout().flush();
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+        let line = match expand(&console, &line) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("{e}");
+                continue;
+            }
+        };
+        match line.as_str() {
+            ":quit" => return,
+            ":help" => println!("{HELP}"),
+            ":history" => {
+                for (i, line) in console.history().iter().enumerate() {
+                    println!("{:>4}  {line}", i + 1);
+                }
+            }
+            ":save" => eprintln!("Missing file"),
+            _ if line.starts_with(":save ") => {
+                let path = line[":save ".len()..].trim();
+                if path.is_empty() {
+                    eprintln!("Missing file");
+                } else if let Err(e) = console.save_transcript(path) {
+                    eprintln!("Failed to save \"{path}\": {e}");
+                } else {
+                    println!("{ANSI_ITALIC}Saved to \"{path}\"{ANSI_RESET}");
+                }
+            }
+            _ if state != Ok(RunState::NeedsInput) => {
+                eprintln!("The program has stopped; `:save <file>` or `:quit`");
+            }
+            _ => {
+                let (output, next) = console.send(&line);
+                show(&output, &next);
+                state = next;
+            }
+        }
+    }
+}
+
+// Resolves history references, echoing the line they stand for
+fn expand(console: &AsciiConsole, line: &str) -> Result<String, String> {
+    let history = console.history();
+    let line = match line.strip_prefix('!') {
+        Some("!") => history.last().ok_or("History is empty")?,
+        Some(n) => {
+            let i: usize = n.parse().map_err(|_| format!("Invalid line: {n}"))?;
+            i.checked_sub(1)
+                .and_then(|i| history.get(i))
+                .ok_or(format!("No line {i} in the history"))?
+        }
+        None => return Ok(line.to_string()),
+    };
+    println!("{line}");
+    Ok(line.clone())
+}
+
+// Shows what the program output, then why it stopped if it isn't waiting
+// for input
+fn show(output: &[AsciiOutput], state: &Result<RunState, IntcodeError>) {
+    for part in output {
+        match part {
+            AsciiOutput::Text(text) => print!("{text}"),
+            AsciiOutput::Value(value) => println!("{ANSI_BOLD}{value}{ANSI_RESET}"),
+        }
+    }
+    match state {
+        Ok(RunState::NeedsInput) => {}
+        Ok(state) => println!("{ANSI_ITALIC}Program stopped: {state:?}{ANSI_RESET}"),
+        Err(err) => eprintln!("Program failed: {err}"),
+    }
+}
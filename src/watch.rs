@@ -0,0 +1,159 @@
+use crate::{Change, Instruction, Intcode, Memory, TraceEvent, Tracer};
+use hashbrown::HashMap;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+// Traces how a machine's memory changes as it runs, and what it outputs, to
+// help find where a program keeps its state (e.g. a game's score). Changes
+// made outside the machine (e.g. with set_code) aren't seen.
+#[derive(Clone)]
+pub struct MemoryWatch {
+    // What memory held before each write, as seen so far
+    memory: Memory,
+    steps: u64,
+    // Writes that changed a value, by step
+    changes: Vec<(u64, Change)>,
+    outputs: Vec<(u64, i64)>,
+}
+
+impl MemoryWatch {
+    pub fn new(intcode: &Intcode) -> Self {
+        Self {
+            memory: intcode.memory().clone(),
+            steps: 0,
+            changes: vec![],
+            outputs: vec![],
+        }
+    }
+
+    // Number of instructions traced
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn changes(&self) -> &[(u64, Change)] {
+        &self.changes
+    }
+
+    pub fn outputs(&self) -> &[(u64, i64)] {
+        &self.outputs
+    }
+
+    // Net changes made during the given steps, by address (leaving out
+    // addresses that ended up back where they started)
+    pub fn changed(&self, steps: Range<u64>) -> Vec<Change> {
+        let mut net: BTreeMap<usize, Change> = BTreeMap::new();
+        for (_, change) in self.changes.iter().filter(|(s, _)| steps.contains(s)) {
+            net.entry(change.addr)
+                .and_modify(|net| net.new = change.new)
+                .or_insert(*change);
+        }
+        net.into_values()
+            .filter(|change| change.old != change.new)
+            .collect()
+    }
+
+    // Number of times each address changed, most often first
+    pub fn hot(&self) -> Vec<(usize, usize)> {
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+        for (_, change) in &self.changes {
+            *counts.entry(change.addr).or_default() += 1;
+        }
+        let mut hot: Vec<_> = counts.into_iter().collect();
+        hot.sort_by_key(|&(addr, count)| (std::cmp::Reverse(count), addr));
+        hot
+    }
+
+    // Addresses (that change at some point) holding the same value as the
+    // selected outputs when they're made, with how many of them they matched,
+    // best first. The selector is given the outputs so far, ending with the
+    // one to select or not.
+    pub fn correlate<F>(&self, selected: F) -> Vec<(usize, usize)>
+    where
+        F: Fn(&[i64]) -> bool,
+    {
+        let mut values: HashMap<usize, i64> = HashMap::new();
+        for (_, change) in self.changes.iter().rev() {
+            values.insert(change.addr, change.old);
+        }
+
+        let mut matches: HashMap<usize, usize> = HashMap::new();
+        let mut changes = self.changes.iter().peekable();
+        let mut outputs = vec![];
+        for &(step, value) in &self.outputs {
+            while let Some((_, change)) = changes.next_if(|(s, _)| *s < step) {
+                values.insert(change.addr, change.new);
+            }
+            outputs.push(value);
+            if selected(&outputs) {
+                for (&addr, _) in values.iter().filter(|(_, v)| **v == value) {
+                    *matches.entry(addr).or_default() += 1;
+                }
+            }
+        }
+
+        let mut matches: Vec<_> = matches.into_iter().collect();
+        matches.sort_by_key(|&(addr, count)| (std::cmp::Reverse(count), addr));
+        matches
+    }
+}
+
+impl Tracer for MemoryWatch {
+    fn trace(&mut self, event: &TraceEvent) {
+        if let Some((addr, new)) = event.write {
+            let old = self.memory.get(addr);
+            if old != new {
+                self.memory.set(addr, new);
+                self.changes.push((self.steps, Change { addr, old, new }));
+            }
+        }
+        if event.instruction == Some(Instruction::Output) {
+            self.outputs.push((self.steps, event.operands()[0]));
+        }
+        self.steps += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Outputs a running total of its inputs, kept at 11
+    const TOTAL: &str = "3,12,1,11,12,11,4,11,1105,1,0,0,0";
+
+    #[test]
+    fn test_watch() {
+        let mut intcode = Intcode::new(TOTAL);
+        let mut watch = MemoryWatch::new(&intcode);
+        let mut input = [5, 3, 0, 4].into_iter();
+        intcode.run_traced(|| input.next(), |_| {}, &mut watch);
+        assert_eq!(watch.steps(), 16);
+        assert_eq!(
+            watch.outputs().iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+            [5, 8, 8, 12]
+        );
+        assert_eq!(watch.hot(), [(12, 4), (11, 3)]);
+
+        // The second input changing the total, and the third set back to 0
+        assert_eq!(
+            watch.changed(4..12),
+            [
+                Change {
+                    addr: 11,
+                    old: 5,
+                    new: 8
+                },
+                Change {
+                    addr: 12,
+                    old: 5,
+                    new: 0
+                }
+            ]
+        );
+        assert_eq!(
+            watch.changed(0..watch.steps()),
+            Intcode::new(TOTAL).memory().diff(intcode.memory())
+        );
+        assert_eq!(watch.correlate(|_| true), [(11, 4), (12, 1)]);
+    }
+}